@@ -3,22 +3,27 @@
 use super::command_handler;
 use crate::{
     behavior_hiding::output_formatting::{FormatStyle, OutputFormatter},
+    repo_hiding::operation::{branch::migrate_flat_refs, stage::upgrade_legacy_index},
     GEET_DIR,
 };
 use clap::{Parser, Subcommand};
-use std::{default, fmt};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
-pub struct CLI {
+pub struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
-enum Commands {
+pub enum Commands {
     /// Create an empty Git repository or reinitialize an existing one
-    Init {},
+    Init {
+        /// Hash algorithm used to name objects (sha1 or sha256)
+        #[arg(long, default_value = "sha1")]
+        hash: String,
+    },
 
     /// Clone a repository from a remote location to a local path
     Clone {
@@ -42,14 +47,60 @@ enum Commands {
     Add {
         /// Path to the file to add
         file_path: String,
+        /// Add files even if they are ignored
+        #[arg(short, long)]
+        force: bool,
     },
 
-    /// Remove a file from the staging area or the repository
+    /// Unstage a file, staging its committed version again
     Remove {
         /// Path to the file to remove
         file_path: String,
     },
 
+    /// Remove files from the next commit and from the working directory
+    Rm {
+        /// Path to the file or directory to remove
+        file_path: String,
+        /// Only stop tracking the files, keeping them in the working directory
+        #[arg(long)]
+        cached: bool,
+        /// Remove the files even if they have changes that would be lost
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Move or rename a file or directory in the working directory and the staging area
+    Mv {
+        /// The tracked file or directory to move
+        source: String,
+        /// The new path, or an existing directory to move the source into
+        destination: String,
+    },
+
+    /// Delete untracked files from the working directory
+    Clean {
+        /// Only list the files that would be deleted
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Delete the files; required unless --dry-run is given
+        #[arg(short, long)]
+        force: bool,
+        /// Delete ignored files as well
+        #[arg(short = 'x')]
+        ignored: bool,
+    },
+
+    /// Show whether paths are ignored by .geetignore or exclude files
+    CheckIgnore {
+        /// Paths to check
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Show the pattern that matches each path, including patterns that re-include it
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
     /// Show all open branch heads in the repository
     Heads {},
 
@@ -57,19 +108,38 @@ enum Commands {
     Status {},
 
     /// Show the commit logs of the repository
-    Log {},
+    Log {
+        /// Revision or range (A..B) to show the history of
+        #[arg(default_value = "HEAD")]
+        revision: String,
+
+        /// Show each commit on one line with a short unique hash
+        #[arg(long)]
+        oneline: bool,
+    },
+
+    /// Show where a ref pointed before each update, newest first
+    Reflog {
+        /// Ref whose log to show; entry n can be used as <ref>@{n}
+        #[arg(default_value = "HEAD")]
+        name: String,
+    },
 
-    /// Show the differences between two commits or a commit and the working directory
+    /// Show the differences between two commits, or the unstaged or staged changes
     Diff {
-        /// Hash of the first commit
-        hash1: String,
-        /// Hash of the second commit
-        hash2: String,
+        /// The newer commit, or a range A..B to show the changes from A to B;
+        /// without it the working directory is compared with the staging area
+        rev1: Option<String>,
+        /// The older commit
+        rev2: Option<String>,
+        /// Compare the staging area with HEAD instead
+        #[arg(long, visible_alias = "staged", conflicts_with_all = ["rev1", "rev2"])]
+        cached: bool,
     },
 
     /// Display the contents of a file at a specific commit
     Cat {
-        /// Path to the file to display
+        /// Path to the file to display, an object hash, or <revision>:<path>
         file_path: String,
     },
 
@@ -86,27 +156,159 @@ enum Commands {
 
     /// Switch branches or restore working tree files
     Checkout {
-        /// Branch name or commit hash to check out
+        /// Branch name, commit hash or revision (e.g. HEAD~2) to check out
         str: String,
 
         /// Flag to create a new branch named <BRANCHNAME>
         #[arg(short, help = "Create a new branch named <BRANCHNAME>")]
         branch: bool,
+
+        /// Commit the new branch starts at with -b; defaults to HEAD
+        start_point: Option<String>,
+
+        /// Take our version of a conflicting path during a merge
+        #[arg(long, conflicts_with_all = ["branch", "theirs"])]
+        ours: bool,
+
+        /// Take their version of a conflicting path during a merge
+        #[arg(long, conflicts_with = "branch")]
+        theirs: bool,
+    },
+
+    /// List, create, rename or delete branches
+    Branch {
+        /// Branch to create, rename or delete
+        name: Option<String>,
+
+        /// Commit a new branch starts at (defaults to HEAD), or the new name with -m
+        start_point: Option<String>,
+
+        /// Rename a branch; with a single name, renames the current branch
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+
+        /// Delete a branch whose commits are all part of HEAD
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Delete a branch even if it is not merged
+        #[arg(short = 'D')]
+        force_delete: bool,
+
+        /// Print the name of the current branch
+        #[arg(long)]
+        show_current: bool,
+
+        /// List the remote-tracking branches recorded by clone, pull and push
+        #[arg(short, long)]
+        remotes: bool,
+    },
+
+    /// List, create, delete or show tags
+    Tag {
+        /// Tag to create, delete or show
+        name: Option<String>,
+
+        /// Commit to tag; defaults to HEAD
+        revision: Option<String>,
+
+        /// Create an annotated tag with a tagger, date and message
+        #[arg(short, long)]
+        annotate: bool,
+
+        /// Message of an annotated tag; implies -a
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Tagger recorded in an annotated tag
+        #[arg(long, default_value = "Anonymous")]
+        tagger: String,
+
+        /// Delete the tag
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Show the tag and the commit it points to
+        #[arg(long)]
+        show: bool,
+    },
+
+    /// Switch to a branch, refusing to discard uncommitted changes
+    Switch {
+        /// Branch to switch to
+        branch: String,
+
+        /// Create the branch before switching to it
+        #[arg(short, long)]
+        create: bool,
+
+        /// Commit the new branch starts at with -c; defaults to HEAD
+        start_point: Option<String>,
     },
 
     /// Merge the changes from another branch into the current branch
-    Merge { from: String },
+    Merge {
+        /// Branch, tag or commit to merge into HEAD
+        #[arg(required_unless_present_any = ["continue_merge", "abort"])]
+        from: Option<String>,
+
+        /// Commit the merge once all conflicts are resolved
+        #[arg(long = "continue", conflicts_with_all = ["from", "abort"])]
+        continue_merge: bool,
+
+        /// Give up on the merge and restore the working directory to HEAD
+        #[arg(long, conflicts_with = "from")]
+        abort: bool,
+
+        /// Message of the merge commit; defaults to "Merge branch '<from>'"
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Author of the merge commit; defaults to "Anonymous"
+        #[arg(short, long, default_value = "Anonymous")]
+        author: String,
+    },
+
+    /// Pack loose objects into a single pack file, storing similar files as deltas
+    Repack {},
+
+    /// Optimize the repository by packing its objects and pruning unreachable ones
+    Gc {
+        /// Delete objects that are not reachable from any ref
+        #[arg(long)]
+        prune: bool,
+
+        /// Only list the objects that would be pruned
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Keep unreachable objects younger than this (e.g. 30m, 12h, 2w, now)
+        #[arg(long, default_value = "2w")]
+        grace: String,
+    },
+
+    /// Verify the integrity of every object and ref in the repository
+    Fsck {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Clean up unnecessary files and optimize the repository TODO: remove from production
     Cleanup {},
 }
 
-pub fn parse_input() {
-    let cli = match CLI::try_parse() {
+// returns a failure exit code when the command could not be parsed or executed
+pub fn parse_input() -> ExitCode {
+    let cli = match Cli::try_parse() {
         Ok(cli) => cli,
         Err(e) => {
-            e.print();
-            return;
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            };
         }
     };
 
@@ -114,22 +316,22 @@ pub fn parse_input() {
 
     if cli.command.is_none() {
         println!("No command provided. Use -h to see usage.");
-        return;
+        return ExitCode::FAILURE;
     }
 
     let command = cli.command.unwrap();
     let command_str = format!("{:?}", command);
     let result = execute_command(&command);
 
-    if result.is_err() {
+    if let Err(e) = result {
         formatter.display_syntax_error(&format!("Error executing command: {}", command_str));
-        println!("Error: {}", result.unwrap_err());
+        println!("Error: {}", e);
+        return ExitCode::FAILURE;
     };
+    ExitCode::SUCCESS
 }
 
 pub fn execute_command(command: &Commands) -> Result<(), String> {
-    let formatter = OutputFormatter::new(FormatStyle::Colored);
-
     // if !is_repo_initialized() && !matches!(command, Commands::Init {} | Commands::Clone { .. }) {
     //     return Err(
     //         "Repository not initialized. Use 'geet init' to create a new repository.".to_string(),
@@ -137,7 +339,7 @@ pub fn execute_command(command: &Commands) -> Result<(), String> {
     // }
     // only check for repo init if the command isn't 'init', 'clone, or 'cleanup'
     match command {
-        Commands::Init {} => return command_handler::init(),
+        Commands::Init { hash } => return command_handler::init(hash),
         Commands::Clone { remote_path } => return command_handler::clone(remote_path),
         Commands::Cleanup {} => return cleanup_helper(),
         _ => {
@@ -146,24 +348,127 @@ pub fn execute_command(command: &Commands) -> Result<(), String> {
                     "Repository not initialized. Use 'geet init' to create a new repository.".to_string(),
                 );
             }
+            // repositories from before ref namespaces are moved to the new layout once
+            migrate_flat_refs()?;
+            // as is an index that lists staged paths instead of their contents
+            upgrade_legacy_index()?;
         }
     }
 
     match command {
-        Commands::Init {} => command_handler::init(),
+        Commands::Init { hash } => command_handler::init(hash),
         Commands::Clone { remote_path } => command_handler::clone(remote_path),
         Commands::Pull { remote_path } => command_handler::pull(remote_path),
         Commands::Push { remote_path } => command_handler::push(remote_path),
-        Commands::Add { file_path } => command_handler::add(file_path),
+        Commands::Add { file_path, force } => command_handler::add(file_path, *force),
         Commands::Remove { file_path } => command_handler::remove(file_path),
+        Commands::Rm {
+            file_path,
+            cached,
+            force,
+        } => command_handler::rm(file_path, *cached, *force),
+        Commands::Mv {
+            source,
+            destination,
+        } => command_handler::mv(source, destination),
+        Commands::Clean {
+            dry_run,
+            force,
+            ignored,
+        } => command_handler::clean(*dry_run, *force, *ignored),
+        Commands::CheckIgnore { paths, verbose } => command_handler::check_ignore(paths, *verbose),
         Commands::Heads {} => command_handler::heads(),
         Commands::Status {} => command_handler::status(),
-        Commands::Log {} => command_handler::log(),
-        Commands::Diff { hash1, hash2 } => command_handler::diff(hash1, hash2),
+        Commands::Log { revision, oneline } => command_handler::log(revision, *oneline),
+        Commands::Reflog { name } => command_handler::reflog(name),
+        Commands::Diff { rev1, rev2, cached } => {
+            command_handler::diff(rev1.as_deref(), rev2.as_deref(), *cached)
+        }
         Commands::Cat { file_path } => command_handler::cat(file_path),
         Commands::Commit { message, author } => command_handler::commit(message, author),
-        Commands::Checkout { str, branch } => command_handler::checkout(str, branch),
-        Commands::Merge { from } => command_handler::merge(from),
+        Commands::Checkout {
+            str,
+            branch,
+            start_point,
+            ours,
+            theirs,
+        } => {
+            if *ours || *theirs {
+                command_handler::checkout_conflict(str, *theirs)
+            } else {
+                command_handler::checkout(str, branch, start_point.as_deref())
+            }
+        }
+        Commands::Branch {
+            name,
+            start_point,
+            rename,
+            delete,
+            force_delete,
+            show_current,
+            remotes,
+        } => {
+            if *show_current {
+                command_handler::show_current_branch()
+            } else if *rename {
+                command_handler::rename_branch(name.as_deref(), start_point.as_deref())
+            } else if *delete || *force_delete {
+                command_handler::delete_branch(name.as_deref(), *force_delete)
+            } else if let Some(name) = name {
+                command_handler::create_branch(name, start_point.as_deref())
+            } else {
+                command_handler::list_branches(*remotes)
+            }
+        }
+        Commands::Tag {
+            name,
+            revision,
+            annotate,
+            message,
+            tagger,
+            delete,
+            show,
+        } => match name {
+            Some(name) if *delete => command_handler::delete_tag(name),
+            Some(name) if *show => command_handler::show_tag(name),
+            Some(name) => command_handler::create_tag(
+                name,
+                revision.as_deref(),
+                *annotate,
+                message.as_deref(),
+                tagger,
+            ),
+            None if *delete || *show => Err("Missing the tag name".to_string()),
+            None => command_handler::list_tags(),
+        },
+        Commands::Switch {
+            branch,
+            create,
+            start_point,
+        } => command_handler::switch(branch, *create, start_point.as_deref()),
+        Commands::Merge {
+            from,
+            continue_merge,
+            abort,
+            message,
+            author,
+        } => {
+            if *continue_merge {
+                command_handler::continue_merge(message.as_deref(), author)
+            } else if *abort {
+                command_handler::abort_merge()
+            } else {
+                let from = from.as_deref().unwrap_or_default();
+                command_handler::merge(from, message.as_deref(), author)
+            }
+        }
+        Commands::Repack {} => command_handler::repack(),
+        Commands::Gc {
+            prune,
+            dry_run,
+            grace,
+        } => command_handler::gc(*prune, *dry_run, grace),
+        Commands::Fsck { json } => command_handler::fsck(*json),
         Commands::Cleanup {} => cleanup_helper(),
     }
 }
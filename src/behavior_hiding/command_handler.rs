@@ -1,31 +1,47 @@
 use crate::{
     file_hiding::{
-        file_log::{does_object_exist, retrieve_object},
+        file_log::{hash_object, retrieve_object, ObjectKind},
+        hasher_checker::HashAlgorithm,
+        ignore::IgnoreRules,
         index,
-        ref_log::Hash,
+        pack::PackStats,
     },
     repo_hiding::{
-        data_type::{Commit, CommitMetadata, RefType},
+        data_type::{Commit, CommitMetadata, RefType, Tag, Tree},
         operation::{
             branch::{
-                checkout_commit,
+                self, checkout_commit, checkout_ref, current_branch,
                 diff::{get_diffs, Diff},
-                list_commits, list_refs,
+                ensure_clean_worktree, get_head, list_commits, list_refs,
+                reflog::read_reflog,
+                switch_branch,
+                tag::{self, get_tag_object},
+            },
+            maintenance::{fsck::fsck_repo, gc_repo, repack_repo},
+            merge::{
+                merge_revision,
+                resolve::{self, checkout_conflict_side, merge_in_progress, ConflictSide},
+                MergeOutcome,
             },
             repo::{clone_repo, init_repo, pull_repo, push_repo},
-            revision::create_revision,
+            revision::{
+                create_revision, get_revision, peel_to_commit, resolve_object, resolve_range,
+                resolve_revision, CommitRange, ShortHashes,
+            },
+            stage,
         },
-        utility::normalize_path,
+        utility::{is_binary, normalize_path, parse_duration},
     },
-    BASE_DIR, OBJECTS_DIR,
+    BASE_DIR,
 };
 use chrono::Utc;
 use colored::Colorize;
-use std::fs;
-use std::process::Command;
+use std::path::PathBuf;
 
-pub fn init() -> Result<(), String> {
-    init_repo(&"default".to_string(), &"main".to_string())?;
+pub fn init(hash: &str) -> Result<(), String> {
+    let algorithm = HashAlgorithm::parse(hash)
+        .ok_or_else(|| format!("Unknown hash algorithm {}, expected sha1 or sha256", hash))?;
+    init_repo("default", "main", algorithm)?;
     Ok(())
 }
 
@@ -49,50 +65,305 @@ pub fn push(remote_path: &String) -> Result<(), String> {
     Ok(())
 }
 
-pub fn add(file_path: &str) -> Result<(), String> {
+pub fn add(file_path: &str, force: bool) -> Result<(), String> {
     let file_path = normalize_path(file_path);
-    index::add(&file_path)?;
+    index::add(&file_path, force)?;
     println!("Added file {} to staging area.", file_path);
     Ok(())
 }
 
 pub fn remove(file_path: &str) -> Result<(), String> {
     let file_path = normalize_path(file_path);
-    index::remove(&file_path)?;
+    stage::unstage(&file_path)?;
     println!("Removed file {} from staging area.", file_path);
     Ok(())
 }
 
+pub fn rm(file_path: &str, cached: bool, force: bool) -> Result<(), String> {
+    let file_path = normalize_path(file_path);
+    for path in stage::remove_paths(&file_path, cached, force)? {
+        println!("rm '{}'", path);
+    }
+    Ok(())
+}
+
+pub fn mv(source: &str, destination: &str) -> Result<(), String> {
+    let moved = stage::move_path(&normalize_path(source), &normalize_path(destination))?;
+    for (old, new) in moved {
+        println!("Renamed {} to {}", old, new);
+    }
+    Ok(())
+}
+
+pub fn clean(dry_run: bool, force: bool, ignored: bool) -> Result<(), String> {
+    if !dry_run && !force {
+        return Err("Refusing to clean without -f/--force or -n/--dry-run".to_string());
+    }
+    for path in stage::clean(ignored, dry_run)? {
+        if dry_run {
+            println!("Would remove {}", path);
+        } else {
+            println!("Removing {}", path);
+        }
+    }
+    Ok(())
+}
+
+/*
+print the paths that are ignored; with `verbose` also the pattern deciding each path, as
+source:line:pattern, including negated patterns that re-include a path. Tracked paths are
+never ignored.
+*/
+pub fn check_ignore(paths: &[String], verbose: bool) -> Result<(), String> {
+    let read_error = |e: std::io::Error| format!("Failed to read ignore files: {}", e);
+    let tracked = index::read_entries().map_err(|e| format!("Failed to read index: {}", e))?;
+    let mut rules = IgnoreRules::load().map_err(read_error)?;
+    for path in paths {
+        let path = PathBuf::from(normalize_path(path));
+        let key = index::index_key(&path);
+        if tracked.contains_key(&key) {
+            continue;
+        }
+        let Some(rule) = rules.check(&key, path.is_dir()).map_err(read_error)? else {
+            continue;
+        };
+        if verbose {
+            println!("{}:{}:{}\t{}", rule.source, rule.line, rule.text, key);
+        } else if !rule.is_negated() {
+            println!("{}", key);
+        }
+    }
+    Ok(())
+}
+
 pub fn status() -> Result<(), String> {
-    // TODO - Implement this part
-    print!("On branch main\n\n");
+    match current_branch()? {
+        Some(branch) => print!("On branch {}\n\n", branch),
+        None => {
+            let head = get_head()?.unwrap_or_default();
+            print!(
+                "HEAD detached at {}\n\n",
+                ShortHashes::new()?.shorten(&head)
+            );
+        }
+    }
 
-    println!("Changes to be committed:");
-    let files = index::get_staged_files();
-    files.iter().for_each(|file| println!("{:?}", file));
-    println!();
+    let merging = merge_in_progress()?;
+    if let Some(merge_head) = &merging {
+        let unmerged = index::get_unmerged();
+        if unmerged.is_empty() {
+            println!("All conflicts fixed but you are still merging.");
+            println!("  (use \"geet merge --continue\" to conclude the merge)\n");
+        } else {
+            let short_hashes = ShortHashes::new()?;
+            println!(
+                "You are merging {}; fix the conflicts and run \"geet merge --continue\"",
+                short_hashes.shorten(merge_head)
+            );
+            println!("  (use \"geet merge --abort\" to abort the merge)\n");
+            println!("Unmerged paths:");
+            for (path, stages) in &unmerged {
+                let line = format!("\t{}: {}", stages.describe(), path).red();
+                println!("{}", line);
+            }
+            println!();
+        }
+    }
 
-    println!("Changes not staged for commit:");
-    let files = index::get_unstaged_files();
-    files.iter().for_each(|file| println!("{:?}", file));
+    // unmerged paths are only listed above until their conflicts are resolved
+    let unmerged: Vec<String> = index::get_unmerged().into_keys().collect();
+    let is_merged = |path: &String| !unmerged.contains(path);
+
+    let staged = stage::staged_diff()?;
+    let mut to_commit: Vec<(String, &str)> = staged
+        .new_files
+        .iter()
+        .map(|path| (path.clone(), "new file"))
+        .chain(
+            staged
+                .modified_files
+                .iter()
+                .map(|(path, _, _)| (path.clone(), "modified")),
+        )
+        .chain(
+            staged
+                .deleted_files
+                .iter()
+                .map(|path| (path.clone(), "deleted")),
+        )
+        .filter(|(path, _)| is_merged(path))
+        .chain(
+            staged
+                .renamed_files
+                .iter()
+                .map(|(old, new)| (format!("{} -> {}", old, new), "renamed")),
+        )
+        .collect();
+    to_commit.sort();
+
+    let worktree = stage::worktree_changes()?;
+    let mut not_staged: Vec<(&String, &str)> = worktree
+        .modified
+        .iter()
+        .map(|path| (path, "modified"))
+        .chain(worktree.deleted.iter().map(|path| (path, "deleted")))
+        .filter(|(path, _)| is_merged(path))
+        .collect();
+    not_staged.sort();
+
+    if !to_commit.is_empty() {
+        println!("Changes to be committed:");
+        for (path, kind) in &to_commit {
+            println!("{}", format!("\t{}: {}", kind, path).green());
+        }
+        println!();
+    }
+    if !not_staged.is_empty() {
+        println!("Changes not staged for commit:");
+        for (path, kind) in &not_staged {
+            println!("{}", format!("\t{}: {}", kind, path).red());
+        }
+        println!();
+    }
+    if !worktree.untracked.is_empty() {
+        println!("Untracked files:");
+        for path in &worktree.untracked {
+            println!("{}", format!("\t{}", path).red());
+        }
+        println!();
+    }
+    if merging.is_none()
+        && unmerged.is_empty()
+        && to_commit.is_empty()
+        && not_staged.is_empty()
+        && worktree.untracked.is_empty()
+    {
+        println!("nothing to commit, working tree clean");
+    }
 
     Ok(())
 }
 
 pub fn heads() -> Result<(), String> {
     let ref_list = list_refs(RefType::Branch)?;
+    let short_hashes = ShortHashes::new()?;
+    let current = current_branch()?;
     println!("Active branches:");
     for _ref in ref_list {
-        println!("{}", _ref.name);
+        let marker = if current.as_ref() == Some(&_ref.name) {
+            "*"
+        } else {
+            " "
+        };
+        match &_ref.commit_hash {
+            Some(hash) => println!("{} {} {}", marker, _ref.name, short_hashes.shorten(hash)),
+            None => println!("{} {}", marker, _ref.name),
+        }
+    }
+    Ok(())
+}
+
+pub fn list_branches(remotes: bool) -> Result<(), String> {
+    let short_hashes = ShortHashes::new()?;
+    let (current, branches) = if remotes {
+        (None, list_refs(RefType::Remote)?)
+    } else {
+        (current_branch()?, list_refs(RefType::Branch)?)
+    };
+    let width = branches.iter().map(|b| b.name.len()).max().unwrap_or(0);
+
+    for branch in &branches {
+        let marker = if current.as_ref() == Some(&branch.name) {
+            "*"
+        } else {
+            " "
+        };
+        let Some(hash) = &branch.commit_hash else {
+            println!("{} {}", marker, branch.name);
+            continue;
+        };
+        let commit = get_revision(hash)?;
+        let message = commit.metadata.message.lines().next().unwrap_or_default();
+        let line = format!(
+            "{} {:width$} {} {}",
+            marker,
+            branch.name,
+            short_hashes.shorten(hash),
+            message,
+            width = width
+        );
+        if marker == "*" {
+            println!("{}", line.green());
+        } else {
+            println!("{}", line);
+        }
     }
     Ok(())
 }
 
-pub fn log() -> Result<(), String> {
-    let commit_map = list_commits("HEAD".to_string(), None)?;
+pub fn create_branch(name: &str, start_point: Option<&str>) -> Result<(), String> {
+    let start = resolve_revision(start_point.unwrap_or("HEAD"))?;
+    branch::create_branch(name, &start)?;
+    println!(
+        "Created branch '{}' at {}",
+        name,
+        ShortHashes::new()?.shorten(&start)
+    );
+    Ok(())
+}
+
+pub fn rename_branch(name: Option<&str>, new_name: Option<&str>) -> Result<(), String> {
+    // with a single name, the current branch gets renamed
+    let (old_name, new_name) = match (name, new_name) {
+        (Some(old_name), Some(new_name)) => (old_name.to_string(), new_name),
+        (Some(new_name), None) => (
+            current_branch()?.ok_or("HEAD is detached, name the branch to rename")?,
+            new_name,
+        ),
+        _ => return Err("Missing the new branch name".to_string()),
+    };
+    branch::rename_branch(&old_name, new_name)?;
+    println!("Renamed branch '{}' to '{}'", old_name, new_name);
+    Ok(())
+}
+
+pub fn delete_branch(name: Option<&str>, force: bool) -> Result<(), String> {
+    let name = name.ok_or("Missing the name of the branch to delete")?;
+    branch::delete_branch(&name.to_string(), force)?;
+    println!("Deleted branch '{}'", name);
+    Ok(())
+}
+
+pub fn show_current_branch() -> Result<(), String> {
+    // a detached HEAD prints nothing, like git
+    if let Some(branch) = current_branch()? {
+        println!("{}", branch);
+    }
+    Ok(())
+}
+
+pub fn log(revision: &str, oneline: bool) -> Result<(), String> {
+    let commit_map = list_commits(&resolve_range(revision)?, None)?;
+
+    let short_hashes = ShortHashes::new()?;
+    if oneline {
+        for (commit_hash, commit) in &commit_map {
+            let message = commit.metadata.message.lines().next().unwrap_or_default();
+            println!("{} {}", short_hashes.shorten(commit_hash).yellow(), message);
+        }
+        return Ok(());
+    }
 
     for (commit_hash, commit) in &commit_map {
         println!("commit {}", commit_hash);
+        if commit.is_merge() {
+            let parents: Vec<String> = commit
+                .parents
+                .iter()
+                .map(|parent| short_hashes.shorten(parent))
+                .collect();
+            println!("\tMerge: {}", parents.join(" "));
+        }
         println!("\tAuthor: {}", commit.metadata.author);
         println!("\tDate: {}", commit.metadata.timestamp);
         println!("\tMessage: {}", commit.metadata.message);
@@ -102,16 +373,111 @@ pub fn log() -> Result<(), String> {
     Ok(())
 }
 
-pub fn diff(hash1: &Hash, hash2: &Hash) -> Result<(), String> {
+pub fn reflog(name: &str) -> Result<(), String> {
+    let (_, entries) = read_reflog(name)?;
+    let short_hashes = ShortHashes::new()?;
+    for (n, entry) in entries.iter().enumerate() {
+        let hash = match &entry.new_hash {
+            Some(hash) => short_hashes.shorten(hash),
+            None => "-".to_string(),
+        };
+        println!(
+            "{} {}@{{{}}}: {} ({}, {})",
+            hash.yellow(),
+            name,
+            n,
+            entry.reason,
+            entry.author,
+            entry.timestamp
+        );
+    }
+    Ok(())
+}
+
+/*
+show the changes between two commits, or without revisions the changes in the working directory
+that are not staged yet, or with `cached` the staged changes the next commit would make
+*/
+pub fn diff(rev1: Option<&str>, rev2: Option<&str>, cached: bool) -> Result<(), String> {
+    if cached {
+        let diff = stage::staged_diff()?;
+        if diff.is_empty() {
+            println!("No changes staged for commit.");
+        }
+        print_diff(&diff, |_, hash| read_blob(hash));
+        return Ok(());
+    }
+    let Some(rev1) = rev1 else {
+        return diff_worktree();
+    };
+
+    // `A..B` shows what changed from A to B, i.e. B is the newer side
+    let (newer, older) = match rev2 {
+        Some(rev2) => (resolve_revision(rev1)?, resolve_revision(rev2)?),
+        None => match resolve_range(rev1)? {
+            CommitRange {
+                from: Some(from),
+                to,
+            } => (to, from),
+            CommitRange { from: None, .. } => {
+                return Err("Diff needs two revisions or a range A..B".to_string())
+            }
+        },
+    };
+
+    let diff = get_diffs(&newer, &older)?;
+    if diff.is_empty() {
+        println!("No changes found between the commits.");
+    }
+    print_diff(&diff, |_, hash| read_blob(hash));
+    Ok(())
+}
+
+// the changes in the working directory against the staged snapshot
+fn diff_worktree() -> Result<(), String> {
+    let changes = stage::worktree_changes()?;
+    let entries = index::read_entries().map_err(|e| format!("Failed to read index: {}", e))?;
+    let mut modified_files = Vec::new();
+    for path in changes.modified {
+        // the index may have changed since it was compared, leaving nothing to diff against
+        let Some(staged) = entries.get(&path) else {
+            continue;
+        };
+        let content =
+            std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let staged = staged.hash.clone();
+        modified_files.push((path, staged, hash_object(ObjectKind::Blob, &content)));
+    }
+    let diff = Diff {
+        deleted_files: changes.deleted,
+        modified_files,
+        new_files: Vec::new(),
+        renamed_files: Vec::new(),
+    };
+
+    if diff.is_empty() {
+        println!("No unstaged changes.");
+    }
+    print_diff(&diff, |path, _| std::fs::read(path).unwrap_or_default());
+    Ok(())
+}
+
+fn read_blob(hash: &str) -> Vec<u8> {
+    retrieve_object(hash).map(|o| o.data).unwrap_or_default()
+}
+
+// print the files of a diff, reading the newer version of a modified file with `read_new`
+fn print_diff(diff: &Diff, read_new: impl Fn(&str, &str) -> Vec<u8>) {
     let Diff {
         deleted_files,
         modified_files,
         new_files,
-    } = get_diffs(hash1, hash2);
+        renamed_files,
+    } = diff;
 
     if !deleted_files.is_empty() {
         println!("Deleted files:");
-        for file in &deleted_files {
+        for file in deleted_files {
             let line = format!("- {}", file).red();
             println!("{}", line);
         }
@@ -120,65 +486,143 @@ pub fn diff(hash1: &Hash, hash2: &Hash) -> Result<(), String> {
 
     if !new_files.is_empty() {
         println!("New files:");
-        for file in &new_files {
+        for file in new_files {
             let line = format!("+ {}", file).green();
             println!("{}", line);
         }
         println!();
     }
 
-    if !modified_files.is_empty() {
-        for (file, hash1, hash2) in &modified_files {
-            let line = format!("> {}", file).yellow();
+    if !renamed_files.is_empty() {
+        println!("Renamed files:");
+        for (old, new) in renamed_files {
+            let line = format!("~ {} -> {}", old, new).cyan();
             println!("{}", line);
-            print_file_diffs(&hash1, &hash2);
         }
+        println!();
     }
 
-    if deleted_files.is_empty() && new_files.is_empty() && modified_files.is_empty() {
-        println!("No changes found between the commits.");
+    if !modified_files.is_empty() {
+        for (file, hash1, hash2) in modified_files {
+            let line = format!("> {}", file).yellow();
+            println!("{}", line);
+            print_content_diff(&read_blob(hash1), &read_new(file, hash2));
+        }
     }
-
-    Ok(())
 }
 
 pub fn cat(path_or_hash: &String) -> Result<(), String> {
     // First try to read as a regular file
     let file_path = normalize_path(path_or_hash);
-    if let Ok(content) = std::fs::read_to_string(file_path) {
-        println!("{}", content);
+    if let Ok(content) = std::fs::read(&file_path) {
+        print_content(&file_path, &content);
         return Ok(());
     }
 
-    // If not a file, try as a hash
-    let content = retrieve_object(path_or_hash).map_err(|_| {
-        format!(
-            "Neither a valid file path nor a valid object hash: {}",
-            path_or_hash
-        )
+    // If not a file, try as a revision, <revision>:<path>, or a full or abbreviated hash
+    let hash = resolve_object(path_or_hash).map_err(|e| {
+        if path_or_hash.contains(':') {
+            e
+        } else {
+            format!(
+                "{} is not a file in the working directory\n{}",
+                path_or_hash, e
+            )
+        }
     })?;
+    let object =
+        retrieve_object(&hash).map_err(|e| format!("Failed to read object {}: {}", hash, e))?;
+
+    // Format the object according to the kind recorded in its header
+    match object.kind {
+        ObjectKind::Commit => print_commit(&hash, &Commit::deserialize(&object)?),
+        ObjectKind::Tree => {
+            let tree = Tree::deserialize(&object)?;
+            for node in tree.nodes {
+                let kind = if node.is_dir { "tree" } else { "blob" };
+                println!("{} {}\t{}", kind, node.hash, node.name);
+            }
+        }
+        ObjectKind::Tag => print_tag(&Tag::deserialize(&object)?),
+        ObjectKind::Blob => print_content(&hash, &object.data),
+    }
+    Ok(())
+}
 
-    // Try to parse as commit for better formatting
-    if let Ok(commit) = serde_json::from_str::<Commit>(&content) {
+pub fn list_tags() -> Result<(), String> {
+    let short_hashes = ShortHashes::new()?;
+    let tags = list_refs(RefType::Tag)?;
+    let width = tags.iter().map(|t| t.name.len()).max().unwrap_or(0);
+
+    for data in &tags {
+        let Some(hash) = &data.commit_hash else {
+            continue;
+        };
+        // annotated tags show their own message, lightweight ones the commit's
+        let commit_hash = peel_to_commit(hash)?;
+        let message = match get_tag_object(data)? {
+            Some(tag) => tag.message,
+            None => get_revision(&commit_hash)?.metadata.message,
+        };
         println!(
-            "Commit: {}\nAuthor: {}\nDate: {}\nMessage: {}",
-            path_or_hash,
-            commit.metadata.author,
-            commit.metadata.timestamp,
-            commit.metadata.message
+            "{:width$} {} {}",
+            data.name,
+            short_hashes.shorten(&commit_hash),
+            message.lines().next().unwrap_or_default(),
+            width = width
         );
-        return Ok(());
     }
+    Ok(())
+}
 
-    // If not a commit, just print the content
-    println!("{}", content);
+pub fn create_tag(
+    name: &str,
+    revision: Option<&str>,
+    annotate: bool,
+    message: Option<&str>,
+    tagger: &str,
+) -> Result<(), String> {
+    let commit_hash = resolve_revision(revision.unwrap_or("HEAD"))?;
+    let short_hash = ShortHashes::new()?.shorten(&commit_hash);
+
+    // like git, a message implies an annotated tag
+    if annotate || message.is_some() {
+        let message = message.ok_or("Annotated tags need a message, pass it with -m")?;
+        tag::create_annotated_tag(name, &commit_hash, tagger, message)?;
+        println!("Created annotated tag '{}' at {}", name, short_hash);
+    } else {
+        tag::create_lightweight_tag(name, &commit_hash)?;
+        println!("Created tag '{}' at {}", name, short_hash);
+    }
     Ok(())
 }
 
-pub fn commit(message: &String, author: &String) -> Result<(), String> {
+pub fn delete_tag(name: &str) -> Result<(), String> {
+    tag::delete_tag(name)?;
+    println!("Deleted tag '{}'", name);
+    Ok(())
+}
+
+pub fn show_tag(name: &str) -> Result<(), String> {
+    let data = tag::get_tag(name)?;
+    if let Some(tag) = get_tag_object(&data)? {
+        print_tag(&tag);
+        println!();
+    }
+    let commit_hash = resolve_revision(&data.full_name())?;
+    print_commit(&commit_hash, &get_revision(&commit_hash)?);
+    Ok(())
+}
+
+pub fn commit(message: &str, author: &str) -> Result<(), String> {
+    // committing during a merge concludes it
+    if merge_in_progress()?.is_some() {
+        return continue_merge(Some(message), author);
+    }
+
     let metadata = CommitMetadata {
-        author: author.clone(),
-        message: message.clone(),
+        author: author.to_string(),
+        message: message.to_string(),
         timestamp: Utc::now().to_rfc3339(),
     };
 
@@ -187,31 +631,236 @@ pub fn commit(message: &String, author: &String) -> Result<(), String> {
     Ok(())
 }
 
-pub fn checkout(str: &String, branch: &bool) -> Result<(), String> {
+pub fn checkout(str: &String, branch: &bool, start_point: Option<&str>) -> Result<(), String> {
     if *branch {
-        println!("Creating and switching to new branch '{}'...", str);
+        let start = resolve_revision(start_point.unwrap_or("HEAD"))?;
+        if get_head()? != Some(start.clone()) {
+            ensure_clean_worktree(&start)?;
+        }
+        let data = branch::create_branch(str, &start)?;
+        checkout_ref(&data.full_name())?;
+        println!("Switched to a new branch '{}'", str);
+        return Ok(());
+    }
+    if start_point.is_some() {
+        return Err("A start point can only be given together with -b".to_string());
+    }
+
+    // a branch name attaches HEAD to the branch, anything else detaches it
+    if let Ok(data) = branch::get_branch(str) {
+        checkout_ref(&data.full_name())?;
+        println!("Switched to branch '{}'", str);
+    } else {
+        let hash = resolve_revision(str)?;
+        checkout_commit(&hash)?;
+        println!("HEAD is now at {} (detached)", hash);
     }
+    Ok(())
+}
 
-    // TODO: handle branches
-    let hash = if does_object_exist(&str) { str } else { str };
+pub fn switch(branch: &String, create: bool, start_point: Option<&str>) -> Result<(), String> {
+    if create {
+        let start = resolve_revision(start_point.unwrap_or("HEAD"))?;
+        if get_head()? != Some(start.clone()) {
+            ensure_clean_worktree(&start)?;
+        }
+        branch::create_branch(branch, &start)?;
+    } else if start_point.is_some() {
+        return Err("A start point can only be given together with -c".to_string());
+    }
 
-    checkout_commit(str)?;
-    println!("Switched to commit {}", hash);
+    switch_branch(branch)?;
+    if create {
+        println!("Switched to a new branch '{}'", branch);
+    } else {
+        println!("Switched to branch '{}'", branch);
+    }
     Ok(())
 }
 
-// TODO
-pub fn merge(from: &Hash) -> Result<(), String> {
-    let to = "HEAD".to_string();
-    let merged_revision = format!("Merged revision {} to {}", from, to);
+pub fn merge(from: &str, message: Option<&str>, author: &str) -> Result<(), String> {
+    let head = get_head()?;
+    match merge_revision(from, author, message)? {
+        MergeOutcome::UpToDate => println!("Already up to date."),
+        MergeOutcome::FastForward(hash) => {
+            let short_hashes = ShortHashes::new()?;
+            let old = head.map(|head| short_hashes.shorten(&head));
+            println!(
+                "Updating {}..{}",
+                old.unwrap_or_default(),
+                short_hashes.shorten(&hash)
+            );
+            println!("Fast-forward");
+        }
+        MergeOutcome::Merged(hash) => {
+            println!(
+                "Merge made by the 'three-way' strategy with Commit ID: {}",
+                hash
+            )
+        }
+        MergeOutcome::Conflicts(conflicts) => {
+            for (path, kind) in &conflicts {
+                println!("CONFLICT ({}): Merge conflict in {}", kind, path);
+            }
+            return Err(
+                "Automatic merge failed; fix the conflicts, mark them with `geet add` \
+                and run `geet merge --continue`."
+                    .into(),
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn continue_merge(message: Option<&str>, author: &str) -> Result<(), String> {
+    let commit_id = resolve::continue_merge(message, author)?;
+    println!("Merge committed with Commit ID: {}", commit_id);
+    Ok(())
+}
+
+pub fn abort_merge() -> Result<(), String> {
+    resolve::abort_merge()?;
+    println!("Merge aborted, the working directory is back at HEAD.");
+    Ok(())
+}
+
+pub fn checkout_conflict(path: &str, theirs: bool) -> Result<(), String> {
+    let side = if theirs {
+        ConflictSide::Theirs
+    } else {
+        ConflictSide::Ours
+    };
+    checkout_conflict_side(&normalize_path(path), side)?;
+    println!(
+        "Updated {} from {} version.",
+        path,
+        if theirs { "their" } else { "our" }
+    );
+    Ok(())
+}
+
+pub fn repack() -> Result<(), String> {
+    let stats = repack_repo()?;
+    print_pack_stats(&stats);
+    Ok(())
+}
+
+pub fn gc(prune: bool, dry_run: bool, grace: &str) -> Result<(), String> {
+    let grace = parse_duration(grace)?;
+    let report = gc_repo(prune, dry_run, grace)?;
+
+    if dry_run {
+        println!(
+            "Would pack {} objects, removing {} loose objects.",
+            report.pack.objects, report.pack.loose_removed
+        );
+        if prune {
+            for (hash, kind) in &report.pruned {
+                println!("Would prune {} {}", kind, hash);
+            }
+            println!(
+                "{} unreachable objects would be pruned, {} kept within the grace period.",
+                report.pruned.len(),
+                report.kept_recent
+            );
+        }
+        return Ok(());
+    }
 
+    print_pack_stats(&report.pack);
+    println!("Packed {} refs.", report.packed_refs);
+    if prune {
+        println!(
+            "Pruned {} unreachable objects, kept {} within the grace period.",
+            report.pruned.len(),
+            report.kept_recent
+        );
+    }
     Ok(())
 }
 
-fn print_file_diffs(hash1: &String, hash2: &String) {
-    // Retrieve content from both versions
-    let content1 = retrieve_object(hash1).unwrap_or_else(|_| String::new());
-    let content2 = retrieve_object(hash2).unwrap_or_else(|_| String::new());
+pub fn fsck(json: bool) -> Result<(), String> {
+    let report = fsck_repo()?;
+
+    if json {
+        let serialized = serde_json::to_string_pretty(&report)
+            .map_err(|e| format!("Failed to serialize report: {}", e))?;
+        println!("{}", serialized);
+    } else {
+        let groups = [
+            ("missing", &report.missing),
+            ("corrupt", &report.corrupt),
+            ("bad-ref", &report.bad_refs),
+            ("dangling", &report.dangling),
+        ];
+        for (label, issues) in groups {
+            for issue in issues {
+                let kind = issue.kind.as_deref().unwrap_or("-");
+                println!("{} {} {} ({})", label, kind, issue.name, issue.detail);
+            }
+        }
+        println!(
+            "checked={} refs={} missing={} corrupt={} bad_refs={} dangling={}",
+            report.checked_objects,
+            report.checked_refs,
+            report.missing.len(),
+            report.corrupt.len(),
+            report.bad_refs.len(),
+            report.dangling.len()
+        );
+    }
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err("Repository integrity check failed".to_string())
+    }
+}
+
+fn print_pack_stats(stats: &PackStats) {
+    if stats.objects == 0 {
+        println!("Nothing to pack.");
+        return;
+    }
+
+    println!(
+        "Packed {} objects ({} stored as deltas) into {} bytes.",
+        stats.objects, stats.deltas, stats.pack_size
+    );
+    println!("Removed {} loose objects.", stats.loose_removed);
+}
+
+fn print_commit(hash: &str, commit: &Commit) {
+    println!(
+        "Commit: {}\nAuthor: {}\nDate: {}\nMessage: {}",
+        hash, commit.metadata.author, commit.metadata.timestamp, commit.metadata.message
+    );
+}
+
+fn print_tag(tag: &Tag) {
+    println!(
+        "Tag: {}\nObject: {}\nTagger: {}\nDate: {}\nMessage: {}",
+        tag.name, tag.target_hash, tag.tagger, tag.timestamp, tag.message
+    );
+}
+
+fn print_content(name: &str, content: &[u8]) {
+    if is_binary(content) {
+        println!("Binary file {} ({} bytes)", name, content.len());
+    } else {
+        println!("{}", String::from_utf8_lossy(content));
+    }
+}
+
+fn print_content_diff(content1: &[u8], content2: &[u8]) {
+    if is_binary(content1) || is_binary(content2) {
+        println!("Binary files differ");
+        println!();
+        return;
+    }
+
+    let content1 = String::from_utf8_lossy(content1);
+    let content2 = String::from_utf8_lossy(content2);
 
     // Split content into lines
     let lines1: Vec<&str> = content1.lines().collect();
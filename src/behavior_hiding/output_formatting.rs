@@ -18,7 +18,7 @@ impl OutputFormatter {
 
     pub fn display_syntax_error(&self, error_message: &str) {
         let formatted_message = match self.format_style {
-            FormatStyle::Plain => format!("{}", error_message),
+            FormatStyle::Plain => error_message.to_string(),
             FormatStyle::Colored => format!("{}", error_message.red()),
         };
         eprintln!("{}", formatted_message); // Displaying error message to stderr
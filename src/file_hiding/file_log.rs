@@ -1,62 +1,356 @@
 // file_hiding/file_log.rs
-use super::index;
+use super::hasher_checker::HasherChecker;
+use super::pack;
 use crate::OBJECTS_DIR;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
-use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, Read, Result, Write};
 use std::path::{Path, PathBuf};  // Use this consolidated import
+use std::time::SystemTime;
 
+/* the kind of data an object holds, written in the object header */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
 
-pub fn hash_object(data: &String) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(data.as_bytes());
-    let hash = hasher.finalize();
-    format!("{:x}", hash)
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "blob" => Some(ObjectKind::Blob),
+            "tree" => Some(ObjectKind::Tree),
+            "commit" => Some(ObjectKind::Commit),
+            "tag" => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
 }
 
-pub fn store_object(data: &String) -> Result<String> {
-    // Generate SHA-1 hash
-    let hash_string = hash_object(data);
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
 
-    // Write data to a file named with its hash
+/* an object read back from the store, with its header already stripped */
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+}
+
+// prefix the data with its "<kind> <size>\0" header
+fn encode_object(kind: ObjectKind, data: &[u8]) -> Vec<u8> {
+    let mut encoded = format!("{} {}\0", kind, data.len()).into_bytes();
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+// parse the header of a decompressed object and check the size it declares
+fn decode_object(encoded: &[u8]) -> Result<Object> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let nul = encoded
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("object header is missing"))?;
+    let header = std::str::from_utf8(&encoded[..nul])
+        .map_err(|_| invalid("object header is not valid text"))?;
+    let (kind, size) = header
+        .split_once(' ')
+        .ok_or_else(|| invalid("object header is malformed"))?;
+
+    let kind = ObjectKind::parse(kind).ok_or_else(|| invalid("unknown object kind"))?;
+    let size: usize = size.parse().map_err(|_| invalid("object size is not a number"))?;
+
+    let data = encoded[nul + 1..].to_vec();
+    if data.len() != size {
+        return Err(invalid("object size does not match its header"));
+    }
+
+    Ok(Object { kind, data })
+}
+
+/*
+objects written before headers existed are bare, uncompressed JSON or file text, named by the
+hash of that text. The kind is inferred as `cat` used to: commits and trees are the JSON shapes
+geet wrote for them, anything else is a blob.
+*/
+fn legacy_object(data: Vec<u8>) -> Object {
+    let json = serde_json::from_slice::<serde_json::Value>(&data).ok();
+    let has = |key: &str| json.as_ref().is_some_and(|value| value.get(key).is_some());
+    let kind = if has("tree_hash") && has("metadata") {
+        ObjectKind::Commit
+    } else if json.as_ref().and_then(|value| value.get("nodes")).is_some_and(|v| v.is_array()) {
+        ObjectKind::Tree
+    } else {
+        ObjectKind::Blob
+    };
+    Object { kind, data }
+}
+
+// re-hash the object and check that it matches the hash it is stored under
+pub fn verify_object(hash: &str, object: &Object) -> bool {
+    HasherChecker::verify_hash(&encode_object(object.kind, &object.data), hash)
+        || HasherChecker::verify_hash(&object.data, hash) // a legacy object, hashed bare
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+// the hash covers the header as well, so a blob and a tree with the same bytes never collide
+pub fn hash_object(kind: ObjectKind, data: &[u8]) -> String {
+    HasherChecker::generate_hash(&encode_object(kind, data))
+}
+
+pub fn store_object(kind: ObjectKind, data: &[u8]) -> Result<String> {
+    // Hash with the repository's algorithm
+    let hash_string = hash_object(kind, data);
+
+    // objects are immutable, so an existing copy already holds the same content. It is touched,
+    // so that gc counts the grace period of an unreachable object from its latest use.
+    let objects_dir = Path::new(OBJECTS_DIR);
+    if let Some(file_path) = find_object(objects_dir, &hash_string) {
+        File::options()
+            .write(true)
+            .open(file_path)?
+            .set_modified(SystemTime::now())?;
+        return Ok(hash_string);
+    }
+    if pack::freshen_packed_object(objects_dir, &hash_string)? {
+        return Ok(hash_string);
+    }
 
-   // let file_path = format!("{}\\{}", OBJECTS_DIR, hash_string);
-   let file_path = PathBuf::from(OBJECTS_DIR).join(&hash_string);
+    store_loose_object(objects_dir, kind, data)
+}
 
-    let mut file = File::create(&file_path)?;
-    file.write_all(data.as_bytes())?;
+// write the object as a loose file even when a pack already holds it
+pub fn store_loose_object(objects_dir: &Path, kind: ObjectKind, data: &[u8]) -> Result<String> {
+    let hash_string = hash_object(kind, data);
 
+    // Write data to a file named with its hash
+    let file_path = object_path(objects_dir, &hash_string)?;
+    if file_path.exists() {
+        return Ok(hash_string);
+    }
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    write_object_file(&file_path, kind, data)?;
     Ok(hash_string)
 }
 
-pub fn retrieve_object(hash: &String) -> Result<String> {
-    if hash.len() < 2 {
+// write an object read from a pack back as a loose file under the hash it is known by,
+// which for a legacy object is not the hash of its encoded form
+pub fn restore_loose_object(objects_dir: &Path, hash: &str, object: &Object) -> Result<()> {
+    let file_path = object_path(objects_dir, hash)?;
+    if file_path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_object_file(&file_path, object.kind, &object.data)
+}
+
+fn write_object_file(file_path: &Path, kind: ObjectKind, data: &[u8]) -> Result<()> {
+    let compressed = compress(&encode_object(kind, data))?;
+    let mut file = File::create(file_path)?;
+    file.write_all(&compressed)
+}
+
+pub fn retrieve_object(hash: &str) -> Result<Object> {
+    check_hash(hash)?;
+    let objects_dir = Path::new(OBJECTS_DIR);
+    if let Some(file_path) = find_object(objects_dir, hash) {
+        return read_loose_object(&file_path, hash);
+    }
+
+    // fall back to the pack files written by repack
+    pack::read_packed_object(objects_dir, hash)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))
+}
+
+pub fn does_object_exist(hash: &str) -> bool {
+    let objects_dir = Path::new(OBJECTS_DIR);
+    check_hash(hash).is_ok()
+        && (find_object(objects_dir, hash).is_some()
+            || pack::is_object_packed(objects_dir, hash).unwrap_or(false))
+}
+
+fn read_loose_object(file_path: &Path, hash: &str) -> Result<Object> {
+    let mut compressed = Vec::new();
+    File::open(file_path)?.read_to_end(&mut compressed)?;
+    match decompress(&compressed).and_then(|data| decode_object(&data)) {
+        Ok(object) => Ok(object),
+        // a file that does not decode but hashes to its name is a legacy object
+        Err(_) if HasherChecker::verify_hash(&compressed, hash) => Ok(legacy_object(compressed)),
+        Err(e) => Err(e),
+    }
+}
+
+// objects are sharded by the first two characters of their hash (objects/xx/yyyy...)
+// so that no single directory ends up with hundreds of thousands of entries
+pub fn object_path(objects_dir: &Path, hash: &str) -> Result<PathBuf> {
+    check_hash(hash)?;
+    Ok(objects_dir.join(&hash[..2]).join(&hash[2..]))
+}
+
+// a hash names a file below the objects directory, so only hex digits can be let through
+fn check_hash(hash: &str) -> Result<()> {
+    if hash.len() < 3 || !is_hex(hash) {
         return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Invalid hash provided",
+            io::ErrorKind::InvalidInput,
+            format!("Invalid hash provided: {:?}", hash),
         ));
     }
+    Ok(())
+}
 
-    let file_path = PathBuf::from(OBJECTS_DIR).join(hash);
-    //let file_path = format!("{}\\{}", OBJECTS_DIR, hash);
+// look for the object in the sharded layout first, then in the flat layout of older repositories
+fn find_object(objects_dir: &Path, hash: &str) -> Option<PathBuf> {
+    [object_path(objects_dir, hash).ok()?, objects_dir.join(hash)]
+        .into_iter()
+        .find(|path| path.is_file())
+}
 
-    if !Path::new(&file_path).exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "File not found"));
+// list the hashes of all objects in the given objects directory, loose or packed
+pub fn list_objects(objects_dir: &Path) -> Result<HashSet<String>> {
+    let mut hashes = list_loose_objects(objects_dir)?;
+    hashes.extend(pack::list_packed_objects(objects_dir)?);
+    Ok(hashes)
+}
+
+// list the hashes of the loose objects in the given objects directory, in either layout
+pub fn list_loose_objects(objects_dir: &Path) -> Result<HashSet<String>> {
+    let mut hashes = HashSet::new();
+    if !objects_dir.is_dir() {
+        return Ok(hashes);
     }
 
-    let mut file = File::open(&file_path)?;
-    let mut data = String::new();
-    file.read_to_string(&mut data)?;
-    Ok(data)
+    for entry in fs::read_dir(objects_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_file() && is_hex(&name) {
+            hashes.insert(name);
+        } else if file_type.is_dir() && name.len() == 2 && is_hex(&name) {
+            for shard_entry in fs::read_dir(entry.path())? {
+                let shard_entry = shard_entry?;
+                let rest = shard_entry.file_name().to_string_lossy().to_string();
+                if shard_entry.file_type()?.is_file() && is_hex(&rest) {
+                    hashes.insert(format!("{}{}", name, rest));
+                }
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+// hashes of all objects, loose or packed, that start with `prefix` (at least two hex digits).
+// Only the shard directory of the prefix is read, so this stays cheap in large repositories.
+pub fn find_objects(objects_dir: &Path, prefix: &str) -> Result<Vec<String>> {
+    let mut hashes = pack::find_packed_objects(objects_dir, prefix)?;
+    if prefix.len() >= 2 && is_hex(prefix) {
+        // the flat directory holds the objects of older repositories
+        let shard = &prefix[..2];
+        let dirs = [(objects_dir.join(shard), shard), (objects_dir.to_path_buf(), "")];
+        for (dir, dir_prefix) in dirs {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                let hash = format!("{}{}", dir_prefix, name);
+                if entry.file_type()?.is_file() && is_hex(&name) && hash.starts_with(prefix) {
+                    hashes.insert(hash);
+                }
+            }
+        }
+    }
+
+    let mut hashes: Vec<String> = hashes.into_iter().collect();
+    hashes.sort();
+    Ok(hashes)
+}
+
+// copy a single object between two objects directories, always writing the sharded layout
+pub fn copy_object(src_dir: &Path, dest_dir: &Path, hash: &str) -> Result<()> {
+    let src_file = find_object(src_dir, hash)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+    let dest_file = object_path(dest_dir, hash)?;
+    if let Some(parent) = dest_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(src_file, dest_file)?;
+    Ok(())
+}
+
+// read a loose object from another objects directory, e.g. while repacking
+pub fn retrieve_loose_object(objects_dir: &Path, hash: &str) -> Result<Object> {
+    let file_path = find_object(objects_dir, hash)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+    read_loose_object(&file_path, hash)
+}
+
+// remove a loose object, along with its shard directory once that is empty
+pub fn delete_loose_object(objects_dir: &Path, hash: &str) -> Result<()> {
+    if let Some(file_path) = find_object(objects_dir, hash) {
+        fs::remove_file(&file_path)?;
+        if let Some(parent) = file_path.parent() {
+            if parent != objects_dir && fs::read_dir(parent)?.next().is_none() {
+                fs::remove_dir(parent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// when the loose object was written, used to give new objects a grace period before pruning
+pub fn loose_object_time(objects_dir: &Path, hash: &str) -> Result<SystemTime> {
+    let file_path = find_object(objects_dir, hash)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+    fs::metadata(file_path)?.modified()
+}
+
+pub fn set_loose_object_time(objects_dir: &Path, hash: &str, time: SystemTime) -> Result<()> {
+    let file_path = find_object(objects_dir, hash)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+    File::options().write(true).open(file_path)?.set_modified(time)
 }
 
-pub fn does_object_exist(hash: &String) -> bool {
-    let object_path= PathBuf::from(OBJECTS_DIR).join(hash);
-   // let object_path = format!("{}\\{}", OBJECTS_DIR, hash);
-   let path = Path::new(object_path.as_os_str());
-  //  let path = Path::new(object_path.as_str());
-    return path.exists();
+fn is_hex(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit())
 }
 
 // Store a file and add it to the index
@@ -78,22 +372,22 @@ pub fn does_object_exist(hash: &String) -> bool {
 // }
 
 // Keep existing functions...
-pub fn delete_data(path: &str) -> Result<()> {
+pub fn delete_data(_path: &str) -> Result<()> {
     todo!()
 }
 
-pub fn list_files(directory: &str) -> Result<Vec<String>> {
+pub fn list_files(_directory: &str) -> Result<Vec<String>> {
     todo!()
 }
 
-pub fn serialize_metadata<T: Serialize>(metadata: &T) -> Result<Vec<u8>> {
+pub fn serialize_metadata<T: Serialize>(_metadata: &T) -> Result<Vec<u8>> {
     todo!()
 }
 
 /*
 deserializes metadata for additional information about file versions.
 */
-pub fn deserialize_metadata<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+pub fn deserialize_metadata<T: DeserializeOwned>(_data: &[u8]) -> Result<T> {
     // TODO: Implement metadata deserialization functionality
     todo!()
 }
@@ -122,14 +416,13 @@ pub fn copy_dir(src: PathBuf, dest: PathBuf) -> io::Result<()> {
 }
 
 // TODO
-trait DeserializeOwned: for<'de> Deserialize<'de> {}
+pub trait DeserializeOwned: for<'de> Deserialize<'de> {}
 // type DeserializationError = SerializationError;
 
 // 4 new methods
 // Stores a serializable object and returns its SHA-1 hash.
 // create store_object retrieve_object to settle the path once for all, so the store_data and retrieve data would be simpler instead of calling path
 
-// redesign retrieve_data to use the first two characters of the hash from the store data instead of the path.
 
 /*
 Test cases:
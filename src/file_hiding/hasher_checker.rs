@@ -1,22 +1,108 @@
 // hasher_checker.rs
-// generate and verify SHA-1 hashes to ensure file content integrity.
+// generate and verify SHA-1 or SHA-256 hashes to ensure file content integrity.
 /*
 add the following [dependencies] to cargo.toml:
 [dependencies] 
 */
 
+use crate::CONFIG_FILE;
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/* the hash function a repository names its objects with, chosen once at init */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    // length of a full hash in hexadecimal digits
+    pub fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 40,
+            HashAlgorithm::Sha256 => 64,
+        }
+    }
+
+    pub fn digest(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha1 => format!("{:x}", Sha1::digest(data)),
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// only the part of the repository config this module cares about
+#[derive(Deserialize)]
+struct HashConfig {
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+}
+
+static REPO_ALGORITHM: OnceLock<HashAlgorithm> = OnceLock::new();
+
+/* the algorithm of the repository in the current directory.
+Repositories created before the setting existed have no config file and use SHA-1.
+The value is only cached once a config file was found, so `init` and `clone` can
+write one after the process started. */
+pub fn repo_algorithm() -> HashAlgorithm {
+    if let Some(algorithm) = REPO_ALGORITHM.get() {
+        return *algorithm;
+    }
+    match read_algorithm(Path::new(CONFIG_FILE)) {
+        Some(algorithm) => *REPO_ALGORITHM.get_or_init(|| algorithm),
+        None => HashAlgorithm::default(),
+    }
+}
+
+// the algorithm of the repository rooted at `repo_path`, e.g. a remote
+pub fn algorithm_of(repo_path: &Path) -> HashAlgorithm {
+    read_algorithm(&repo_path.join(CONFIG_FILE)).unwrap_or_default()
+}
+
+fn read_algorithm(config_path: &Path) -> Option<HashAlgorithm> {
+    let data = fs::read(config_path).ok()?;
+    let config: HashConfig = serde_json::from_slice(&data).ok()?;
+    Some(config.hash_algorithm)
+}
 
 #[derive(Debug)]
 pub struct HasherChecker;
 
 impl HasherChecker {
-    // creates an SHA-1 hash for the provided data and returns it as a hexadecimal string
+    // creates a hash of the provided data with the repository's algorithm and returns it as a hexadecimal string
     // enough for small to moderate data inputs
     pub fn generate_hash(data: &[u8]) -> String {
-        let mut hasher = Sha1::new();
-        hasher.update(data);
-        format!("{:x}", hasher.finalize())
+        repo_algorithm().digest(data)
     }
 
     /* check the hash of the provided data against the expected hash
@@ -25,6 +111,12 @@ impl HasherChecker {
         let calculated_hash = Self::generate_hash(data);
         calculated_hash == expected_hash
     }
+
+    // true if `hash` is a full lowercase hexadecimal hash of the repository's algorithm
+    pub fn is_valid_hash(hash: &str) -> bool {
+        hash.len() == repo_algorithm().hex_len()
+            && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
 }
 
 /* 
@@ -44,7 +136,6 @@ for commit, add, and checkout commands
 */
 
 // TODO: Add error handling or logging for hash mismatch cases in `verify_hash` if needed.
-// TODO: Abstract SHA-1 implementation to allow easy switching to SHA-256 or other algorithms if needed.
 // TODO: Add test cases for:
 // 1. Edge case with large data for `generate_hash`.
 // 2. `verify_hash` with invalid hash formats (optional, based on use case).
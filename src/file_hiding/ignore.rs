@@ -0,0 +1,289 @@
+// file_hiding/ignore.rs
+// decide which untracked files to leave alone, from gitignore-style pattern files.
+/*
+patterns are read from, lowest precedence first:
+- the user's global excludes file: $GEET_EXCLUDES_FILE, or geet/ignore in $XDG_CONFIG_HOME
+  (~/.config when unset)
+- .geet/info/exclude, for patterns of this repository that are not committed
+- the .geetignore of the repository root, then that of every directory down to the path
+within each file later patterns take precedence, and the last pattern matching a path decides.
+*/
+
+use crate::EXCLUDE_FILE;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const IGNORE_FILE: &str = ".geetignore";
+
+/* one pattern of an ignore file */
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    pub source: String, // the file the pattern comes from
+    pub line: usize,
+    pub text: String, // the pattern as written
+    pattern: String,  // the glob, without `!`, leading `/` and trailing `/`
+    base: String,     // the directory the file applies to, "" for the whole repository
+    negated: bool,    // `!pattern` re-includes what an earlier pattern ignored
+    dir_only: bool,   // `pattern/` only matches directories
+    anchored: bool,   // a pattern with a `/` matches from `base`, otherwise any file name below it
+}
+
+impl IgnoreRule {
+    // parse a line of an ignore file; None for blank lines and comments
+    fn parse(line: &str, base: &str, source: &str, number: usize) -> Option<IgnoreRule> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // trailing spaces are dropped unless escaped with a backslash
+        let trimmed = line.trim_end_matches(' ');
+        let text = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+            &line[..trimmed.len() + 1]
+        } else {
+            trimmed
+        };
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+
+        let negated = text.starts_with('!');
+        let pattern = if negated { &text[1..] } else { text };
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            source: source.to_string(),
+            line: number,
+            text: text.to_string(),
+            pattern: pattern.to_string(),
+            base: base.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    // true if the pattern matches `path`, a slash-separated path from the repository root
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+        let subject = if self.anchored {
+            relative
+        } else {
+            relative.rsplit('/').next().unwrap_or(relative)
+        };
+        glob_match(self.pattern.as_bytes(), subject.as_bytes())
+    }
+}
+
+/* the ignore rules of the repository, with the .geetignore of each directory read when needed */
+pub struct IgnoreRules {
+    excludes: Vec<IgnoreRule>,                   // global excludes, then .geet/info/exclude
+    directories: HashMap<String, Vec<IgnoreRule>>, // .geetignore patterns by directory
+}
+
+impl IgnoreRules {
+    pub fn load() -> io::Result<Self> {
+        let mut excludes = Vec::new();
+        if let Some(global) = global_excludes_file() {
+            excludes.extend(read_rules(&global.to_string_lossy(), "")?);
+        }
+        excludes.extend(read_rules(EXCLUDE_FILE, "")?);
+        Ok(IgnoreRules {
+            excludes,
+            directories: HashMap::new(),
+        })
+    }
+
+    /*
+    the pattern that decides whether `path` itself is ignored: an ignoring pattern, a negated one
+    that re-includes it, or None when no pattern matches. Directories above the path are not
+    looked at, see `check`.
+    */
+    pub fn matching_rule(&mut self, path: &str, is_dir: bool) -> io::Result<Option<&IgnoreRule>> {
+        // the directories whose .geetignore applies, from the root down
+        let mut dirs = vec![String::new()];
+        let mut parts: Vec<&str> = path.split('/').collect();
+        parts.pop();
+        for n in 1..=parts.len() {
+            dirs.push(parts[..n].join("/"));
+        }
+        for dir in &dirs {
+            if !self.directories.contains_key(dir) {
+                let file = if dir.is_empty() {
+                    IGNORE_FILE.to_string()
+                } else {
+                    format!("{}/{}", dir, IGNORE_FILE)
+                };
+                let rules = read_rules(&file, dir)?;
+                self.directories.insert(dir.clone(), rules);
+            }
+        }
+
+        let nested = dirs.iter().rev().flat_map(|dir| self.directories[dir].iter().rev());
+        Ok(nested
+            .chain(self.excludes.iter().rev())
+            .find(|rule| rule.matches(path, is_dir)))
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> io::Result<bool> {
+        Ok(self
+            .matching_rule(path, is_dir)?
+            .is_some_and(|rule| !rule.negated))
+    }
+
+    /*
+    like `matching_rule`, but a path inside an ignored directory is ignored by that directory's
+    pattern: as with git, nothing below an ignored directory can be re-included.
+    */
+    pub fn check(&mut self, path: &str, is_dir: bool) -> io::Result<Option<IgnoreRule>> {
+        let parts: Vec<&str> = path.split('/').collect();
+        for n in 1..parts.len() {
+            let dir = parts[..n].join("/");
+            if let Some(rule) = self.matching_rule(&dir, true)? {
+                if !rule.negated {
+                    return Ok(Some(rule.clone()));
+                }
+            }
+        }
+        Ok(self.matching_rule(path, is_dir)?.cloned())
+    }
+}
+
+// the user's excludes file: $GEET_EXCLUDES_FILE, else geet/ignore in the user's config directory
+fn global_excludes_file() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let Some(file) = var("GEET_EXCLUDES_FILE") {
+        return Some(PathBuf::from(file));
+    }
+    let config = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("geet").join("ignore"))
+}
+
+// the patterns of an ignore file applying below `base`; a missing file has none
+fn read_rules(file: &str, base: &str) -> io::Result<Vec<IgnoreRule>> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| IgnoreRule::parse(line, base, file, n + 1))
+        .collect())
+}
+
+// match a path against a glob: `*` and `?` match within one path component, `[...]` matches one
+// character of a set or range (`[!...]` one outside it), `\` escapes the next character, and `**`
+// matches across components: `**/` any leading directories, `/**` everything below a directory
+// and `/**/` zero or more directories in between.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.starts_with(b"**") => {
+            let rest = &pattern[2..];
+            if rest.is_empty() {
+                return true;
+            }
+            match rest.strip_prefix(b"/") {
+                Some(rest) => {
+                    glob_match(rest, text)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .any(|(n, &c)| c == b'/' && glob_match(rest, &text[n + 1..]))
+                }
+                None => match_star(rest, text),
+            }
+        }
+        Some(b'*') => match_star(&pattern[1..], text),
+        Some(b'?') => {
+            text.first().is_some_and(|&c| c != b'/') && glob_match(&pattern[1..], &text[1..])
+        }
+        Some(b'[') => {
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            match match_class(pattern, c) {
+                Some((matched, len)) => matched && glob_match(&pattern[len..], &text[1..]),
+                // an unterminated class is a literal `[`
+                None => c == b'[' && glob_match(&pattern[1..], &text[1..]),
+            }
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+// `*` followed by `rest`: any run of characters within the current path component
+fn match_star(rest: &[u8], text: &[u8]) -> bool {
+    for n in 0..=text.len() {
+        if glob_match(rest, &text[n..]) {
+            return true;
+        }
+        if n < text.len() && text[n] == b'/' {
+            break;
+        }
+    }
+    false
+}
+
+// whether `c` is in the class at the start of `pattern`, and the length of the class
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut n = 1;
+    let negated = matches!(pattern.get(n), Some(b'!') | Some(b'^'));
+    if negated {
+        n += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(n)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if start == b'\\' {
+            n += 1;
+            start = *pattern.get(n)?;
+        }
+        match (pattern.get(n + 1), pattern.get(n + 2)) {
+            (Some(b'-'), Some(&end)) if end != b']' => {
+                matched |= (start..=end).contains(&c);
+                n += 3;
+            }
+            _ => {
+                matched |= start == c;
+                n += 1;
+            }
+        }
+    }
+    Some((matched != negated && c != b'/', n + 1))
+}
@@ -1,83 +1,370 @@
-use crate::{BASE_DIR, GEET_DIR, INDEX_FILE, OBJECTS_DIR};
-use core::hash;
-use serde_json::{self, Value};
-use std::collections::HashSet;
+use crate::{BASE_DIR, GEET_DIR, INDEX_FILE};
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::file_log::{does_object_exist, hash_object};
+use super::file_log::{hash_object, store_object, ObjectKind};
+use super::hasher_checker::HasherChecker;
+use super::ignore::IgnoreRules;
+use super::ref_log::Hash;
 
-fn read_index() -> std::io::Result<HashSet<PathBuf>> {
-    match File::open(INDEX_FILE) {
-        Ok(mut file) => {
-            let mut content = String::new();
-            file.read_to_string(&mut content)?;
-            Ok(serde_json::from_str(&content).unwrap_or_default())
+// the index is a magic header followed by the bincode-encoded `Index`; older ones are JSON
+const INDEX_MAGIC: &[u8] = b"GEETINDX";
+
+// modes of the files in the index and in trees, as git writes them
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+
+/* the three versions of a path a merge could not combine; None where a side has no such file */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConflictStages {
+    pub base: Option<Hash>,
+    pub ours: Option<Hash>,
+    pub theirs: Option<Hash>,
+}
+
+impl ConflictStages {
+    // how each side changed the path, as shown by status
+    pub fn describe(&self) -> &'static str {
+        match (&self.base, &self.ours, &self.theirs) {
+            (None, Some(_), None) => "added by us",
+            (None, None, Some(_)) => "added by them",
+            (_, None, _) => "deleted by us",
+            (_, _, None) => "deleted by them",
+            (None, _, _) => "both added",
+            _ => "both modified",
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            // Create parent directory if it doesn't exist
-            if let Some(parent) = Path::new(INDEX_FILE).parent() {
-                fs::create_dir_all(parent)?;
-            }
+    }
+}
+
+/* what the file system reported about a file when its entry was last checked */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime: (i64, u32), // seconds and nanoseconds since the epoch
+    pub ctime: (i64, u32),
+    pub inode: u64,
+}
+
+impl FileStat {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        FileStat {
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec() as u32),
+            ctime: (metadata.ctime(), metadata.ctime_nsec() as u32),
+            inode: metadata.ino(),
+        }
+    }
 
-            // Create and initialize the file with an empty array
-            let mut file = File::create(INDEX_FILE)?;
-            file.write_all(b"[]")?;
-            Ok(HashSet::new())
+    #[cfg(not(unix))]
+    fn of(metadata: &fs::Metadata) -> Self {
+        let since_epoch = |time: io::Result<SystemTime>| {
+            let time = time
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+            let time = time.unwrap_or_default();
+            (time.as_secs() as i64, time.subsec_nanos())
+        };
+        FileStat {
+            size: metadata.len(),
+            mtime: since_epoch(metadata.modified()),
+            ctime: since_epoch(metadata.created()),
+            inode: 0,
         }
-        Err(e) => Err(e),
     }
 }
 
-fn write_index(paths: &HashSet<PathBuf>) -> std::io::Result<()> {
-    let serialized = serde_json::to_string(paths)?;
-    let mut file = File::create(INDEX_FILE)?;
-    file.write_all(serialized.as_bytes())?;
-    Ok(())
+/*
+the staged snapshot of one file: the blob written when it was added and its mode.
+`stat` caches the file's stat info from when its contents were last found to match the blob,
+so unchanged files need not be read and hashed again; None when nothing is cached.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub hash: Hash,
+    pub mode: u32,
+    #[serde(default)]
+    pub stat: Option<FileStat>,
+}
+
+impl IndexEntry {
+    // an entry for a blob as found in a tree, without cached stat info
+    pub fn new(hash: Hash, mode: u32) -> Self {
+        IndexEntry {
+            hash,
+            mode,
+            stat: None,
+        }
+    }
+
+    // true if both entries stage the same blob with the same mode, whatever their stat info
+    pub fn same_contents(&self, other: &IndexEntry) -> bool {
+        self.hash == other.hash && self.mode == other.mode
+    }
+}
+
+/*
+the staging area: the snapshot of every tracked file for the next commit by slash-separated
+repository-relative path, and while a merge is in progress the paths that still have conflicts.
+Older repositories store only the list of staged paths, see `legacy_staged_files`.
+*/
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Index {
+    entries: BTreeMap<String, IndexEntry>,
+    #[serde(default)]
+    unmerged: BTreeMap<String, ConflictStages>, // by index key, like `entries`
+}
+
+/* the formats written before the index recorded file contents */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyIndex {
+    Paths(Vec<PathBuf>),
+    Staged { staged: Vec<PathBuf> },
+}
+
+fn read_index() -> std::io::Result<Index> {
+    let content = match fs::read(INDEX_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Index::default()),
+        Err(e) => return Err(e),
+    };
+    if let Some(encoded) = content.strip_prefix(INDEX_MAGIC) {
+        return bincode::deserialize(encoded)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    // an index written as JSON is read once more and converted on the next write
+    if content.trim_ascii().is_empty() {
+        return Ok(Index::default());
+    }
+    serde_json::from_slice(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_index(index: &Index) -> std::io::Result<()> {
+    // Create parent directory if it doesn't exist
+    if let Some(parent) = Path::new(INDEX_FILE).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut serialized = INDEX_MAGIC.to_vec();
+    serialized.extend(bincode::serialize(index).map_err(io::Error::other)?);
+
+    // written to `index.lock` and renamed over the index, so a reader never sees half an index;
+    // the lock cannot be created while another process is writing the index
+    let lock_path = Path::new(INDEX_FILE).with_extension("lock");
+    let mut file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(
+                e.kind(),
+                format!(
+                    "{} exists, another geet process is writing the index. \
+                    If none is running, remove the file and try again",
+                    lock_path.display()
+                ),
+            ),
+            _ => e,
+        })?;
+    let written = file
+        .write_all(&serialized)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&lock_path, INDEX_FILE));
+    if written.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+    written
+}
+
+/*
+the paths staged in an index written before it recorded file contents, or None when the index
+is current. A missing index counts as an old one with nothing staged, so that it gets rebuilt.
+*/
+pub fn legacy_staged_files() -> std::io::Result<Option<Vec<PathBuf>>> {
+    let content = match fs::read(INDEX_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Vec::new())),
+        Err(e) => return Err(e),
+    };
+    if content.starts_with(INDEX_MAGIC)
+        || content.trim_ascii().is_empty()
+        || serde_json::from_slice::<Index>(&content).is_ok()
+    {
+        return Ok(None);
+    }
+    match serde_json::from_slice::<LegacyIndex>(&content) {
+        Ok(LegacyIndex::Paths(staged)) | Ok(LegacyIndex::Staged { staged }) => Ok(Some(staged)),
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+    }
+}
+
+pub fn read_entries() -> std::io::Result<BTreeMap<String, IndexEntry>> {
+    Ok(read_index()?.entries)
+}
+
+// replace the whole snapshot, keeping any recorded conflicts
+pub fn write_entries(entries: BTreeMap<String, IndexEntry>) -> std::io::Result<()> {
+    // an index in an older format has no conflicts to keep and is simply replaced
+    let unmerged = read_index().map(|index| index.unmerged).unwrap_or_default();
+    write_index(&Index { entries, unmerged })
+}
+
+// the index key of a path: relative to the repository root with `/` separators
+pub fn index_key(path: &Path) -> String {
+    let path = path.strip_prefix(BASE_DIR).unwrap_or(path);
+    path.to_string_lossy().replace('\\', "/")
 }
 
-pub fn clear_index() -> std::io::Result<()> {
-    let index = HashSet::new();
+// record the paths a merge left with conflicts, replacing any recorded before
+pub fn set_unmerged(unmerged: BTreeMap<String, ConflictStages>) -> std::io::Result<()> {
+    let mut index = read_index()?;
+    index.unmerged = unmerged;
     write_index(&index)
 }
 
-fn is_file_changed(path: &Path) -> bool {
-    let mut file = File::open(path).unwrap();
+pub fn get_unmerged() -> BTreeMap<String, ConflictStages> {
+    read_index().unwrap_or_default().unmerged
+}
 
-    let mut content = String::new();
-    file.read_to_string(&mut content).unwrap();
+// the mode a file is committed with: executable when any execute bit is set
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
 
-    let hash = hash_object(&content);
-    return !does_object_exist(&hash);
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    MODE_FILE
 }
 
-fn get_files_recursively(path: &Path) -> std::io::Result<Vec<PathBuf>> {
-    // Skip the geet directory
-    if path.starts_with(GEET_DIR) {
-        return Ok(Vec::new());
+/*
+the entry a file in the working directory would be staged as, without storing its blob.
+When the file's stat info matches the cached one of `cached`, the file is not read and the cached
+hash is reused; otherwise the file is read and hashed, and its contents are returned as well.
+The stat info is taken before reading, so a write racing with the read changes it and is noticed
+next time. A file modified during the current second is not given stat info at all: it could be
+modified again within the same second without its timestamps changing ("racy" timestamps).
+*/
+fn worktree_entry(
+    path: &Path,
+    cached: Option<&IndexEntry>,
+) -> std::io::Result<(IndexEntry, Option<Vec<u8>>)> {
+    let metadata = fs::metadata(path)?;
+    let stat = FileStat::of(&metadata);
+    let mode = file_mode(&metadata);
+    if let Some(cached) = cached.filter(|cached| cached.stat.as_ref() == Some(&stat)) {
+        let entry = IndexEntry {
+            mode,
+            ..cached.clone()
+        };
+        return Ok((entry, None));
     }
 
-    let mut files = Vec::new();
-    if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            files.extend(get_files_recursively(&entry.path())?);
+    let content = fs::read(path)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let is_racy = stat.mtime.0 >= now || stat.ctime.0 >= now;
+    let mut hash = hash_object(ObjectKind::Blob, &content);
+    // unchanged contents of a legacy blob, which is named by the hash of the bare contents
+    if let Some(cached) = cached.filter(|cached| cached.hash != hash) {
+        if HasherChecker::verify_hash(&content, &cached.hash) {
+            hash = cached.hash.clone();
         }
     }
+    let entry = IndexEntry {
+        hash,
+        mode,
+        stat: (!is_racy).then_some(stat),
+    };
+    Ok((entry, Some(content)))
+}
 
-    if path.is_file() && is_file_changed(path) {
-        files.push(path.to_path_buf());
+/* a walk over the working directory that leaves out ignored files the index does not track */
+struct Walk<'a> {
+    tracked: &'a BTreeMap<String, IndexEntry>,
+    rules: IgnoreRules,
+    include_ignored: bool, // list ignored files as well
+}
+
+impl Walk<'_> {
+    fn new(tracked: &BTreeMap<String, IndexEntry>, include_ignored: bool) -> io::Result<Walk<'_>> {
+        Ok(Walk {
+            tracked,
+            rules: IgnoreRules::load()?,
+            include_ignored,
+        })
     }
 
-    Ok(files)
+    // true if the index tracks a file below the directory `dir`
+    fn tracks_below(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        self.tracked
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|(key, _)| key.starts_with(&prefix))
+    }
+
+    fn get_files_recursively(&mut self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        self.walk(path, false, &mut files)?;
+        Ok(files)
+    }
+
+    // `ignored` is set below an ignored directory, where only tracked files are listed
+    fn walk(&mut self, path: &Path, ignored: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        // Skip the geet directory, which is reached as ./.geet when walking from the base directory
+        if path
+            .strip_prefix(BASE_DIR)
+            .unwrap_or(path)
+            .starts_with(GEET_DIR)
+        {
+            return Ok(());
+        }
+
+        let key = index_key(path);
+        if path.is_dir() {
+            let ignored = ignored || (!key.is_empty() && self.rules.is_ignored(&key, true)?);
+            if ignored && !self.include_ignored && !self.tracks_below(&key) {
+                return Ok(());
+            }
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                self.walk(&entry.path(), ignored, files)?;
+            }
+        } else if path.is_file() {
+            let listed = self.include_ignored
+                || self.tracked.contains_key(&key)
+                || !(ignored || self.rules.is_ignored(&key, false)?);
+            if listed {
+                files.push(path.to_path_buf());
+            }
+        }
+        Ok(())
+    }
 }
 
-pub fn add(path: &str) -> Result<(), String> {
+/*
+stage the current contents of a file, or of every file under a directory: each file's blob is
+stored now and its hash recorded, so later edits are not committed until added again.
+Tracked files under the path that no longer exist are dropped from the snapshot.
+Ignored files are left out unless `force`; naming an ignored path that is not tracked is an error.
+*/
+pub fn add(path: &str, force: bool) -> Result<(), String> {
     let path = Path::new(path);
-    if !path.exists() {
-        return Err("File or directory not found".to_string());
-    }
 
     // Use error handling instead of unwrap
     let mut index = match read_index() {
@@ -85,12 +372,62 @@ pub fn add(path: &str) -> Result<(), String> {
         Err(e) => return Err(format!("Failed to read index: {}", e)),
     };
 
-    let files = match get_files_recursively(path) {
+    // adding an unmerged path marks its conflict as resolved, even when the file was deleted
+    let prefix = index_key(path);
+    let resolved: Vec<String> = index
+        .unmerged
+        .keys()
+        .filter(|key| Path::new(key).starts_with(&prefix))
+        .cloned()
+        .collect();
+    let tracked: Vec<String> = index
+        .entries
+        .keys()
+        .filter(|key| Path::new(key).starts_with(&prefix))
+        .cloned()
+        .collect();
+    if !path.exists() && resolved.is_empty() && tracked.is_empty() {
+        return Err("File or directory not found".to_string());
+    }
+    for unmerged in resolved {
+        index.unmerged.remove(&unmerged);
+    }
+    for key in tracked {
+        if !Path::new(&key).exists() {
+            index.entries.remove(&key);
+        }
+    }
+
+    let mut walk = Walk::new(&index.entries, force)
+        .map_err(|e| format!("Failed to read ignore files: {}", e))?;
+    let key = index_key(path);
+    if !force && path.exists() && !index.entries.contains_key(&key) && !walk.tracks_below(&key) {
+        let rule = walk
+            .rules
+            .check(&key, path.is_dir())
+            .map_err(|e| format!("Failed to read ignore files: {}", e))?;
+        if let Some(rule) = rule.filter(|rule| !rule.is_negated()) {
+            return Err(format!(
+                "The path {} is ignored by {}:{}:{}, use --force to add it anyway",
+                key, rule.source, rule.line, rule.text
+            ));
+        }
+    }
+    let files = match walk.get_files_recursively(path) {
         Ok(files) => files,
         Err(e) => return Err(format!("Failed to process files: {}", e)),
     };
 
-    index.extend(files);
+    for file in files {
+        let key = index_key(&file);
+        let (entry, content) = worktree_entry(&file, index.entries.get(&key))
+            .map_err(|e| format!("Failed to read {}: {}", key, e))?;
+        if let Some(content) = content {
+            store_object(ObjectKind::Blob, &content)
+                .map_err(|e| format!("Failed to store {}: {}", key, e))?;
+        }
+        index.entries.insert(key, entry);
+    }
 
     match write_index(&index) {
         Ok(_) => Ok(()),
@@ -98,47 +435,79 @@ pub fn add(path: &str) -> Result<(), String> {
     }
 }
 
-pub fn remove(path: &str) -> Result<(), String> {
-    let path = Path::new(path);
-    if !path.exists() {
-        return Err("File or directory not found".to_string());
-    }
+/* how the working directory differs from the staged snapshot, by index key */
+#[derive(Debug, Default)]
+pub struct WorktreeChanges {
+    pub modified: Vec<String>, // tracked files whose contents or mode changed since staged
+    pub deleted: Vec<String>,  // tracked files missing from the working directory
+    pub untracked: Vec<String>, // files the index does not know
+}
 
-    let mut index = read_index().unwrap();
-    let files = get_files_recursively(path).unwrap();
-    for file in files {
-        index.remove(&file);
+impl WorktreeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.deleted.is_empty() && self.untracked.is_empty()
     }
-
-    write_index(&index).unwrap();
-    Ok(())
 }
 
-// return true if file is not changed, or if the file is changed and staged
-pub fn contains(path: &Path) -> bool {
-    if !is_file_changed(path) {
-        return true;
+/*
+compare every file of the working directory with its index entry, path by path.
+Files found unchanged get their stat info cached in the index, so they are not hashed next time.
+*/
+pub fn worktree_changes() -> std::io::Result<WorktreeChanges> {
+    let mut index = read_index()?;
+    let mut changes = WorktreeChanges::default();
+    let mut seen = HashSet::new();
+    let mut refreshed = false;
+    let files = Walk::new(&index.entries, false)?.get_files_recursively(Path::new(BASE_DIR))?;
+    for file in files {
+        let key = index_key(&file);
+        let Some(staged) = index.entries.get_mut(&key) else {
+            changes.untracked.push(key);
+            continue;
+        };
+        let (entry, _) = worktree_entry(&file, Some(staged))?;
+        if !entry.same_contents(staged) {
+            changes.modified.push(key.clone());
+        } else if entry.stat != staged.stat {
+            *staged = entry;
+            refreshed = true;
+        }
+        seen.insert(key);
     }
+    changes.deleted = index
+        .entries
+        .keys()
+        .filter(|key| !seen.contains(*key))
+        .cloned()
+        .collect();
+    changes.modified.sort();
+    changes.untracked.sort();
 
-    let index = read_index().unwrap();
-    index.contains(&path.to_path_buf())
-}
-
-pub fn is_stage_empty() -> bool {
-    let index = read_index().unwrap();
-    index.is_empty()
+    // the cache only saves work, failing to store it is not an error
+    if refreshed {
+        let _ = write_index(&index);
+    }
+    Ok(changes)
 }
 
-pub fn get_staged_files() -> Vec<PathBuf> {
-    read_index().unwrap().into_iter().collect()
+/*
+the files of the working directory: every tracked file that exists, and the untracked files
+that are not ignored (with `include_ignored`, the ignored ones as well)
+*/
+pub fn worktree_files(include_ignored: bool) -> std::io::Result<Vec<PathBuf>> {
+    let index = read_index()?;
+    let mut walk = Walk::new(&index.entries, include_ignored)?;
+    let mut files = walk.get_files_recursively(Path::new(BASE_DIR))?;
+    files.sort();
+    Ok(files)
 }
 
-pub fn get_unstaged_files() -> Vec<PathBuf> {
-    let staged_files = get_staged_files();
-    let path = Path::new(BASE_DIR);
-    get_files_recursively(path)
-        .unwrap()
-        .into_iter()
-        .filter(|f| !staged_files.contains(f))
-        .collect()
+// the untracked files of the working directory by index key, with or without ignored ones
+pub fn untracked_files(include_ignored: bool) -> std::io::Result<Vec<String>> {
+    let index = read_index()?;
+    Ok(worktree_files(include_ignored)?
+        .iter()
+        .map(|file| index_key(file))
+        .filter(|key| !index.entries.contains_key(key))
+        .collect())
 }
@@ -0,0 +1,40 @@
+use crate::{MERGE_HEAD_FILE, MERGE_MSG_FILE};
+use std::fs;
+use std::io;
+
+use super::ref_log::Hash;
+
+/// Records a merge that stopped on conflicts: the commit being merged and the message
+/// its merge commit will get.
+pub fn store_merge_state(merge_head: &Hash, message: &str) -> io::Result<()> {
+    fs::write(MERGE_MSG_FILE, message)?;
+    fs::write(MERGE_HEAD_FILE, format!("{}\n", merge_head))
+}
+
+/// Reads the commit being merged, or None when no merge is in progress.
+pub fn read_merge_head() -> io::Result<Option<Hash>> {
+    match fs::read_to_string(MERGE_HEAD_FILE) {
+        Ok(data) => Ok(Some(data.trim().to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the message prepared for the merge commit.
+pub fn read_merge_message() -> io::Result<String> {
+    match fs::read_to_string(MERGE_MSG_FILE) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// Forgets the merge in progress.
+pub fn clear_merge_state() -> io::Result<()> {
+    for file in [MERGE_HEAD_FILE, MERGE_MSG_FILE] {
+        match fs::remove_file(file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
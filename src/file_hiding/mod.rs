@@ -1,5 +1,8 @@
 pub mod file_log;
 pub mod hasher_checker;
+pub mod ignore;
 pub mod ref_log;
 pub mod user_data;
 pub mod index;
+pub mod merge_state;
+pub mod pack;
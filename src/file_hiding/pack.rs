@@ -0,0 +1,607 @@
+// file_hiding/pack.rs
+// store many objects in a single pack file, with similar blobs kept as deltas against each other.
+/*
+a pack lives in `objects/pack/` as two files:
+- pack-<checksum>.pack: a magic header followed by bincode-encoded `PackEntry` records
+- pack-<checksum>.idx:  a bincode-encoded `PackIndex` mapping each hash to its record
+*/
+
+use super::file_log::{
+    compress, decompress, delete_loose_object, list_loose_objects, retrieve_loose_object, Object,
+    ObjectKind,
+};
+use super::hasher_checker::HasherChecker;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const PACK_MAGIC: &[u8] = b"GEETPACK";
+
+// number of earlier blobs tried as delta bases for each blob
+const DELTA_WINDOW: usize = 10;
+// longest chain of deltas that has to be resolved to read an object
+const MAX_DELTA_DEPTH: usize = 10;
+// size of the blocks used to find matching runs between two blobs
+const BLOCK_SIZE: usize = 16;
+
+/* a single object in a pack; `base` is set when `data` holds a delta instead of the full content */
+#[derive(Serialize, Deserialize, Debug)]
+struct PackEntry {
+    kind: ObjectKind,
+    base: Option<String>,
+    data: Vec<u8>, // zlib-compressed content or delta
+}
+
+/* byte range of every entry in the matching pack file */
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PackIndex {
+    entries: BTreeMap<String, (u64, u64)>, // hash -> (offset, length)
+}
+
+/* instructions that rebuild a blob from its base */
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum DeltaOp {
+    Copy { offset: u64, len: u64 },
+    Insert(Vec<u8>),
+}
+
+/* summary of a repack, shown to the user */
+#[derive(Debug, Default)]
+pub struct PackStats {
+    pub objects: usize,
+    pub deltas: usize,
+    pub loose_removed: usize,
+    pub pack_size: u64,
+}
+
+fn pack_dir(objects_dir: &Path) -> PathBuf {
+    objects_dir.join("pack")
+}
+
+// the pack file that goes with the given index file
+fn pack_file_for(index_path: &Path) -> PathBuf {
+    index_path.with_extension("pack")
+}
+
+// all index files in the pack directory
+fn list_index_files(objects_dir: &Path) -> Result<Vec<PathBuf>> {
+    let dir = pack_dir(objects_dir);
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_index(index_path: &Path) -> Result<PackIndex> {
+    let data = fs::read(index_path)?;
+    bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/* the index of one pack, with the path it was read from */
+struct LoadedPack {
+    index_path: PathBuf,
+    index: PackIndex,
+}
+
+/*
+the pack indexes of each objects directory, read once per process: fsck, gc and push look up
+every object, and reading every index again for each lookup made them quadratic.
+Whatever adds or removes packs forgets the directory, so its indexes are read again.
+*/
+static LOADED_PACKS: Mutex<BTreeMap<PathBuf, Arc<Vec<LoadedPack>>>> = Mutex::new(BTreeMap::new());
+
+fn loaded_packs(objects_dir: &Path) -> Result<Arc<Vec<LoadedPack>>> {
+    let mut loaded = LOADED_PACKS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(packs) = loaded.get(objects_dir) {
+        return Ok(Arc::clone(packs));
+    }
+
+    let mut packs = Vec::new();
+    for index_path in list_index_files(objects_dir)? {
+        let index = read_index(&index_path)?;
+        packs.push(LoadedPack { index_path, index });
+    }
+    let packs = Arc::new(packs);
+    loaded.insert(objects_dir.to_path_buf(), Arc::clone(&packs));
+    Ok(packs)
+}
+
+fn forget_packs(objects_dir: &Path) {
+    let mut loaded = LOADED_PACKS.lock().unwrap_or_else(|e| e.into_inner());
+    loaded.remove(objects_dir);
+}
+
+fn read_entry(pack_path: &Path, offset: u64, length: u64) -> Result<PackEntry> {
+    let mut file = File::open(pack_path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; length as usize];
+    file.read_exact(&mut buffer)?;
+    bincode::deserialize(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// read an object out of one pack, resolving its delta chain inside that same pack
+fn read_from_pack(
+    index_path: &Path,
+    index: &PackIndex,
+    hash: &str,
+    depth: usize,
+) -> Result<Object> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    if depth > MAX_DELTA_DEPTH {
+        return Err(invalid(format!("delta chain too deep at {}", hash)));
+    }
+
+    let (offset, length) = index
+        .entries
+        .get(hash)
+        .ok_or_else(|| invalid(format!("{} is missing from {}", hash, index_path.display())))?;
+    let entry = read_entry(&pack_file_for(index_path), *offset, *length)?;
+    let data = decompress(&entry.data)?;
+
+    match entry.base {
+        None => Ok(Object {
+            kind: entry.kind,
+            data,
+        }),
+        Some(base_hash) => {
+            let base = read_from_pack(index_path, index, &base_hash, depth + 1)?;
+            let ops: Vec<DeltaOp> = bincode::deserialize(&data)
+                .map_err(|e| invalid(format!("corrupt delta for {}: {}", hash, e)))?;
+            Ok(Object {
+                kind: entry.kind,
+                data: apply_delta(&base.data, &ops)?,
+            })
+        }
+    }
+}
+
+// look the object up in every pack of the objects directory
+pub fn read_packed_object(objects_dir: &Path, hash: &str) -> Result<Option<Object>> {
+    for pack in loaded_packs(objects_dir)?.iter() {
+        if pack.index.entries.contains_key(hash) {
+            return read_from_pack(&pack.index_path, &pack.index, hash, 0).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+pub fn is_object_packed(objects_dir: &Path, hash: &str) -> Result<bool> {
+    Ok(loaded_packs(objects_dir)?
+        .iter()
+        .any(|pack| pack.index.entries.contains_key(hash)))
+}
+
+// give the pack holding the object a new modification time; false when no pack holds it
+pub fn freshen_packed_object(objects_dir: &Path, hash: &str) -> Result<bool> {
+    for pack in loaded_packs(objects_dir)?.iter() {
+        if pack.index.entries.contains_key(hash) {
+            File::options()
+                .write(true)
+                .open(pack_file_for(&pack.index_path))?
+                .set_modified(SystemTime::now())?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// hashes of the packed objects that start with `prefix`
+pub fn find_packed_objects(objects_dir: &Path, prefix: &str) -> Result<HashSet<String>> {
+    let mut hashes = HashSet::new();
+    for pack in loaded_packs(objects_dir)?.iter() {
+        let matches = pack
+            .index
+            .entries
+            .range(prefix.to_string()..)
+            .map(|(hash, _)| hash)
+            .take_while(|hash| hash.starts_with(prefix));
+        hashes.extend(matches.cloned());
+    }
+    Ok(hashes)
+}
+
+pub fn list_packed_objects(objects_dir: &Path) -> Result<HashSet<String>> {
+    let mut hashes = HashSet::new();
+    for pack in loaded_packs(objects_dir)?.iter() {
+        hashes.extend(pack.index.entries.keys().cloned());
+    }
+    Ok(hashes)
+}
+
+// copy the pack and index files the destination doesn't have yet; packs are named by checksum
+pub fn copy_packs(src_dir: &Path, dest_dir: &Path) -> Result<Vec<String>> {
+    forget_packs(dest_dir);
+    let mut copied = Vec::new();
+    for index_path in list_index_files(src_dir)? {
+        let pack_path = pack_file_for(&index_path);
+        let index_name = index_path.file_name().unwrap();
+        let pack_name = pack_path.file_name().unwrap();
+
+        let dest_pack_dir = pack_dir(dest_dir);
+        if dest_pack_dir.join(index_name).exists() {
+            continue;
+        }
+
+        fs::create_dir_all(&dest_pack_dir)?;
+        // copy the pack first so that an index never points at a missing pack
+        fs::copy(&pack_path, dest_pack_dir.join(pack_name))?;
+        fs::copy(&index_path, dest_pack_dir.join(index_name))?;
+        copied.push(pack_name.to_string_lossy().to_string());
+    }
+    Ok(copied)
+}
+
+// the time each packed object was written, taken from the pack that holds it
+pub fn packed_object_times(objects_dir: &Path) -> Result<HashMap<String, SystemTime>> {
+    let mut times = HashMap::new();
+    for pack in loaded_packs(objects_dir)?.iter() {
+        let modified = fs::metadata(pack_file_for(&pack.index_path))?.modified()?;
+        for hash in pack.index.entries.keys() {
+            times.insert(hash.clone(), modified);
+        }
+    }
+    Ok(times)
+}
+
+// what repack() would do, without writing anything: the objects it would pack and the loose
+// objects it would remove. Deltas and the pack size are only known once the pack is built.
+pub fn repack_plan(objects_dir: &Path, keep: Option<&HashSet<String>>) -> Result<PackStats> {
+    let is_kept = |hash: &String| keep.is_none_or(|keep| keep.contains(hash));
+    let loose: HashSet<String> = list_loose_objects(objects_dir)?
+        .into_iter()
+        .filter(is_kept)
+        .collect();
+    let mut objects = loose.clone();
+    for pack in loaded_packs(objects_dir)?.iter() {
+        objects.extend(
+            pack.index
+                .entries
+                .keys()
+                .filter(|hash| is_kept(hash))
+                .cloned(),
+        );
+    }
+    Ok(PackStats {
+        objects: objects.len(),
+        loose_removed: loose.len(),
+        ..PackStats::default()
+    })
+}
+
+/*
+pack every object in the objects directory into a single new pack, then remove the loose
+objects and old packs it replaces. When `keep` is given, only those objects are packed:
+other loose objects are left where they are and other packed objects are dropped.
+Objects are streamed into the pack one at a time, so only the delta window is held in memory.
+*/
+pub fn repack(objects_dir: &Path, keep: Option<&HashSet<String>>) -> Result<PackStats> {
+    let is_kept = |hash: &String| keep.is_none_or(|keep| keep.contains(hash));
+    let loose: HashSet<String> = list_loose_objects(objects_dir)?
+        .into_iter()
+        .filter(is_kept)
+        .collect();
+    let old_packs = loaded_packs(objects_dir)?;
+    let sources = PackSources {
+        objects_dir,
+        loose: &loose,
+        packs: &old_packs,
+    };
+
+    // a first pass reads every object, loose or packed, only for the kind and size that
+    // decide the order it is packed in
+    let mut order = Vec::new();
+    let mut listed = HashSet::new();
+    let packed = old_packs.iter().flat_map(|pack| pack.index.entries.keys());
+    for hash in loose.iter().chain(packed) {
+        if is_kept(hash) && listed.insert(hash) {
+            let object = sources.read(hash)?;
+            order.push((object.kind, object.data.len(), hash.clone()));
+        }
+    }
+
+    // largest blobs first, so that smaller versions are stored as deltas against bigger ones
+    order.sort_by(|a, b| {
+        (a.0.as_str().cmp(b.0.as_str()))
+            .then(b.1.cmp(&a.1))
+            .then(a.2.cmp(&b.2))
+    });
+
+    let mut stats = PackStats::default();
+    let new_index = if order.is_empty() {
+        None
+    } else {
+        let hashes = order.into_iter().map(|(_, _, hash)| hash);
+        Some(write_pack(&sources, hashes, &mut stats)?)
+    };
+
+    // the new pack holds everything we kept, so the old copies can go
+    for pack in old_packs.iter() {
+        if Some(&pack.index_path) != new_index.as_ref() {
+            fs::remove_file(pack_file_for(&pack.index_path))?;
+            fs::remove_file(&pack.index_path)?;
+        }
+    }
+    forget_packs(objects_dir);
+    for hash in &loose {
+        delete_loose_object(objects_dir, hash)?;
+        stats.loose_removed += 1;
+    }
+
+    Ok(stats)
+}
+
+/* where the objects of a repack are read from: the loose objects and the old packs */
+struct PackSources<'a> {
+    objects_dir: &'a Path,
+    loose: &'a HashSet<String>,
+    packs: &'a [LoadedPack],
+}
+
+impl PackSources<'_> {
+    fn read(&self, hash: &String) -> Result<Object> {
+        if self.loose.contains(hash) {
+            return retrieve_loose_object(self.objects_dir, hash);
+        }
+        let pack = self
+            .packs
+            .iter()
+            .find(|pack| pack.index.entries.contains_key(hash))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, hash.clone()))?;
+        read_from_pack(&pack.index_path, &pack.index, hash, 0)
+    }
+}
+
+/*
+write the objects into a new pack in the given order, trying the previous objects of the
+delta window as bases for each blob. The pack is written to a temporary file and renamed into
+place, index last, so readers never see a half-written pack.
+*/
+fn write_pack(
+    sources: &PackSources,
+    hashes: impl Iterator<Item = String>,
+    stats: &mut PackStats,
+) -> Result<PathBuf> {
+    let dir = pack_dir(sources.objects_dir);
+    fs::create_dir_all(&dir)?;
+    let tmp_pack_path = dir.join(format!("tmp-{}.pack", std::process::id()));
+    let mut pack_file = BufWriter::new(File::create(&tmp_pack_path)?);
+    pack_file.write_all(PACK_MAGIC)?;
+    let mut offset = PACK_MAGIC.len() as u64;
+
+    let mut index = PackIndex::default();
+    let mut window: VecDeque<(String, Object, usize)> = VecDeque::with_capacity(DELTA_WINDOW);
+    for hash in hashes {
+        let object = sources.read(&hash)?;
+        let mut best: Option<(&String, usize, Vec<u8>)> = None;
+
+        if object.kind == ObjectKind::Blob {
+            for (candidate, base, depth) in &window {
+                if base.kind != ObjectKind::Blob || *depth >= MAX_DELTA_DEPTH {
+                    continue;
+                }
+
+                let ops = compute_delta(&base.data, &object.data);
+                let delta = bincode::serialize(&ops).map_err(io::Error::other)?;
+                // only worth it when the delta is well under the size of the blob itself
+                if delta.len() < object.data.len() / 2
+                    && best
+                        .as_ref()
+                        .is_none_or(|(_, _, current)| delta.len() < current.len())
+                {
+                    best = Some((candidate, *depth, delta));
+                }
+            }
+        }
+
+        let (entry, depth) = match best {
+            Some((base, base_depth, delta)) => {
+                stats.deltas += 1;
+                let entry = PackEntry {
+                    kind: object.kind,
+                    base: Some(base.clone()),
+                    data: compress(&delta)?,
+                };
+                (entry, base_depth + 1)
+            }
+            None => {
+                let entry = PackEntry {
+                    kind: object.kind,
+                    base: None,
+                    data: compress(&object.data)?,
+                };
+                (entry, 0)
+            }
+        };
+
+        let encoded = bincode::serialize(&entry).map_err(io::Error::other)?;
+        pack_file.write_all(&encoded)?;
+        index
+            .entries
+            .insert(hash.clone(), (offset, encoded.len() as u64));
+        offset += encoded.len() as u64;
+        stats.objects += 1;
+
+        if window.len() == DELTA_WINDOW {
+            window.pop_front();
+        }
+        window.push_back((hash, object, depth));
+    }
+    pack_file.into_inner()?.sync_all()?;
+    stats.pack_size = offset;
+
+    // repacking the same objects gives the same index, and that pack is already in place
+    let index_data = bincode::serialize(&index).map_err(io::Error::other)?;
+    let name = format!("pack-{}", HasherChecker::generate_hash(&index_data));
+    let pack_path = dir.join(format!("{}.pack", name));
+    let index_path = dir.join(format!("{}.idx", name));
+    if index_path.exists() {
+        fs::remove_file(&tmp_pack_path)?;
+        return Ok(index_path);
+    }
+
+    fs::rename(&tmp_pack_path, &pack_path)?;
+    let tmp_index_path = index_path.with_extension("tmp");
+    let mut index_file = File::create(&tmp_index_path)?;
+    index_file.write_all(&index_data)?;
+    index_file.sync_all()?;
+    fs::rename(&tmp_index_path, &index_path)?;
+    Ok(index_path)
+}
+
+// describe `target` as runs copied from `base` and literal inserts
+fn compute_delta(base: &[u8], target: &[u8]) -> Vec<DeltaOp> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[offset..offset + BLOCK_SIZE])
+            .or_insert(offset);
+    }
+
+    let mut ops = Vec::new();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut i = 0;
+
+    while i < target.len() {
+        let matched = if i + BLOCK_SIZE <= target.len() {
+            blocks.get(&target[i..i + BLOCK_SIZE]).copied()
+        } else {
+            None
+        };
+
+        let Some(offset) = matched else {
+            pending.push(target[i]);
+            i += 1;
+            continue;
+        };
+
+        // extend the match forwards, then backwards over bytes we were about to insert
+        let mut len = BLOCK_SIZE;
+        while offset + len < base.len()
+            && i + len < target.len()
+            && base[offset + len] == target[i + len]
+        {
+            len += 1;
+        }
+        let mut back = 0;
+        while back < pending.len()
+            && back < offset
+            && base[offset - back - 1] == target[i - back - 1]
+        {
+            back += 1;
+        }
+        pending.truncate(pending.len() - back);
+
+        if !pending.is_empty() {
+            ops.push(DeltaOp::Insert(std::mem::take(&mut pending)));
+        }
+        ops.push(DeltaOp::Copy {
+            offset: (offset - back) as u64,
+            len: (len + back) as u64,
+        });
+        i += len;
+    }
+
+    if !pending.is_empty() {
+        ops.push(DeltaOp::Insert(pending));
+    }
+    ops
+}
+
+fn apply_delta(base: &[u8], ops: &[DeltaOp]) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let (start, end) = (*offset as usize, (*offset + *len) as usize);
+                let run = base.get(start..end).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "delta copies past the end of its base",
+                    )
+                })?;
+                result.extend_from_slice(run);
+            }
+            DeltaOp::Insert(bytes) => result.extend_from_slice(bytes),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<DeltaOp> {
+        let ops = compute_delta(base, target);
+        assert_eq!(apply_delta(base, &ops).unwrap(), target);
+        ops
+    }
+
+    // text of `lines` numbered lines, long enough to hold many delta blocks
+    fn numbered_lines(lines: usize) -> Vec<u8> {
+        (0..lines)
+            .map(|n| format!("line number {}\n", n))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn delta_round_trips_edits() {
+        let base = numbered_lines(200);
+        let mut inserted = base.clone();
+        inserted.splice(1000..1000, b"a new line in the middle\n".iter().copied());
+        let mut removed = base.clone();
+        removed.drain(500..900);
+        let mut appended = base.clone();
+        appended.extend_from_slice(b"one more line\n");
+
+        for target in [&base, &inserted, &removed, &appended] {
+            let ops = round_trip(&base, target);
+            assert!(ops.iter().any(|op| matches!(op, DeltaOp::Copy { .. })));
+        }
+    }
+
+    #[test]
+    fn delta_round_trips_edge_cases() {
+        let base = numbered_lines(50);
+        round_trip(&base, b"");
+        round_trip(b"", &base);
+        round_trip(b"", b"");
+        round_trip(b"short", b"shorter than a block");
+        round_trip(&base, &numbered_lines(50)[3..]);
+        round_trip(&[0, 255, 0, 255], &[255; 100]);
+    }
+
+    #[test]
+    fn identical_data_is_a_single_copy() {
+        let base = numbered_lines(100);
+        let ops = round_trip(&base, &base);
+        assert_eq!(
+            ops,
+            vec![DeltaOp::Copy {
+                offset: 0,
+                len: base.len() as u64
+            }]
+        );
+    }
+
+    #[test]
+    fn delta_past_the_end_of_its_base_is_rejected() {
+        let ops = [DeltaOp::Copy { offset: 4, len: 10 }];
+        assert!(apply_delta(b"too short", &ops).is_err());
+    }
+}
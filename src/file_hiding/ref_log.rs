@@ -1,27 +1,42 @@
-use crate::REFS_DIR;
+use crate::{GEET_DIR, LOGS_DIR};
+use std::collections::BTreeMap;
 use std::fs::{self, File};
+use std::fs::OpenOptions;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 
 pub type Hash = String;
 
-pub fn store_ref(name: &String, data: &String) -> io::Result<()> {
-    // Ensure the refs directory exists
-    fs::create_dir_all(REFS_DIR)?;
+/*
+refs are named by their path inside `./geet`: `HEAD`, `refs/heads/main`, `refs/tags/v1.0`
+or `refs/remotes/origin/main`. Branch names with slashes become nested directories.
+*/
+fn ref_path(name: &str) -> PathBuf {
+    PathBuf::from(GEET_DIR).join(name)
+}
 
-    // Construct the path to the reference file using PathBuf
-    let path = PathBuf::from(REFS_DIR).join(name);
+/*
+`gc` moves refs into a single `packed-refs` file of `<hash> <full name>` lines, so a repository
+with thousands of tags does not need thousands of files. A loose ref file always overrides the
+packed entry of the same name; symbolic refs such as HEAD are never packed.
+*/
+const PACKED_REFS: &str = "packed-refs";
 
-    // Open the file and write the serialized reference data
-    let mut file = File::create(&path)?;
-    file.write_all(data.as_bytes())?;
+/* a ref as it is stored: the contents of its own file, or its line of packed-refs */
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredRef {
+    Loose(String),
+    Packed(Hash),
+}
 
-    Ok(())
+/// Writes a Ref object to `./geet` through its lock, replacing whatever it held.
+pub fn store_ref(name: &str, data: &str) -> io::Result<()> {
+    RefLock::acquire(name)?.commit(data)
 }
 /*
 /// Creates a new Ref object and writes it to `./geet/refs`.
-pub fn store_ref(name: &String, data: &String) -> io::Result<()> {
+pub fn store_ref(name: &str, data: &str) -> io::Result<()> {
     // Create all necessary parent directories
     fs::create_dir_all(REFS_DIR)?;
     let path = format!("{}\\{}", REFS_DIR, name);
@@ -30,21 +45,398 @@ pub fn store_ref(name: &String, data: &String) -> io::Result<()> {
     Ok(())
 }*/
 
-/// Retrieves a Ref object from `./geet/refs`.
-pub fn retrieve_ref(name: &str) -> io::Result<String> {
-    let path = PathBuf::from(REFS_DIR).join(name);
+/// Retrieves a Ref object from `./geet`, loose or packed.
+pub fn retrieve_ref(name: &str) -> io::Result<StoredRef> {
+    retrieve_ref_in(Path::new(GEET_DIR), name)
+}
+
+/// Retrieves a Ref object from the `.geet` directory of any repository, loose or packed.
+pub fn retrieve_ref_in(geet_dir: &Path, name: &str) -> io::Result<StoredRef> {
+    if let Some(data) = read_loose_ref(&geet_dir.join(name))? {
+        return Ok(StoredRef::Loose(data));
+    }
+    match read_packed_refs_in(geet_dir)?.remove(name) {
+        Some(hash) => Ok(StoredRef::Packed(hash)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "ref not found")),
+    }
+}
+
+fn read_loose_ref(path: &Path) -> io::Result<Option<String>> {
    // let path = format!("{}\\{}", REFS_DIR, name);
-    let mut file = File::open(&path)?;
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
 
     let mut data = String::new();
     file.read_to_string(&mut data)?;
-    Ok(data)
+    Ok(Some(data))
+}
+
+/// Checks whether a Ref object with the given name exists in `./geet`, loose or packed.
+pub fn ref_exists(name: &str) -> bool {
+    ref_path(name).is_file()
+        || read_packed_refs().is_ok_and(|packed| packed.contains_key(name))
+}
+
+/// Reads the packed refs of `./geet` by full name.
+pub fn read_packed_refs() -> io::Result<BTreeMap<String, Hash>> {
+    read_packed_refs_in(Path::new(GEET_DIR))
+}
+
+/// Reads the packed refs of the `.geet` directory of any repository by full name.
+pub fn read_packed_refs_in(geet_dir: &Path) -> io::Result<BTreeMap<String, Hash>> {
+    let data = read_loose_ref(&geet_dir.join(PACKED_REFS))?.unwrap_or_default();
+    parse_packed_refs(&data)
+}
+
+fn parse_packed_refs(data: &str) -> io::Result<BTreeMap<String, Hash>> {
+    let mut refs = BTreeMap::new();
+    for line in data.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let (hash, name) = line.split_once(' ').ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed line in packed-refs: {}", line),
+            )
+        })?;
+        refs.insert(name.to_string(), hash.to_string());
+    }
+    Ok(refs)
 }
 
-/// Deletes a Ref object from `./geet/refs`.
+/// Changes the packed refs of `./geet` under the packed-refs lock, returning what `change` returns.
+pub fn update_packed_refs<T>(
+    change: impl FnOnce(&mut BTreeMap<String, Hash>) -> T,
+) -> io::Result<T> {
+    update_packed_refs_in(Path::new(GEET_DIR), change)
+}
+
+fn update_packed_refs_in<T>(
+    geet_dir: &Path,
+    change: impl FnOnce(&mut BTreeMap<String, Hash>) -> T,
+) -> io::Result<T> {
+    let lock = RefLock::acquire_in(geet_dir, PACKED_REFS)?;
+    let data = read_loose_ref(&lock.path)?.unwrap_or_default();
+    let mut refs = parse_packed_refs(&data)?;
+    let result = change(&mut refs);
+    lock.commit(&format_packed_refs(&refs))?;
+    Ok(result)
+}
+
+fn format_packed_refs(refs: &BTreeMap<String, Hash>) -> String {
+    let mut packed = String::from("# geet packed-refs\n");
+    for (name, hash) in refs {
+        packed.push_str(&format!("{} {}\n", hash, name));
+    }
+    packed
+}
+
+/// Deletes a Ref object from `./geet` through its lock, along with the directories it leaves
+/// empty. The namespace directories such as `refs/heads` are kept.
 pub fn delete_ref(name: &str) -> io::Result<()> {
-    let path = PathBuf::from(REFS_DIR).join(name);
     //let path = format!("{}\\{}", REFS_DIR, name);
-    std::fs::remove_file(&path)?;
+    RefLock::acquire(name)?.delete()
+}
+
+/*
+an exclusive lock on a ref, held as `<ref>.lock` next to it while the ref is updated.
+The new contents are written to the lock file and renamed over the ref, so a reader sees
+either the old or the new ref, never a half-written one, and a second process trying to
+update the same ref fails instead of interleaving with the first.
+The lock is released when it is dropped, whether or not it was committed.
+*/
+pub struct RefLock {
+    name: String,
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    geet_dir: PathBuf,
+    committed: bool,
+}
+
+impl RefLock {
+    /// Locks a ref of `./geet`.
+    pub fn acquire(name: &str) -> io::Result<Self> {
+        Self::acquire_in(Path::new(GEET_DIR), name)
+    }
+
+    /// Locks a ref in the `.geet` directory of any repository.
+    pub fn acquire_in(geet_dir: &Path, name: &str) -> io::Result<Self> {
+        let path = geet_dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{} exists, another geet process is updating the ref. \
+                         If none is running, remove the file and try again",
+                        lock_path.display()
+                    ),
+                ),
+                _ => e,
+            })?;
+
+        Ok(Self {
+            name: name.to_string(),
+            path,
+            lock_path,
+            file: Some(file),
+            geet_dir: geet_dir.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// Reads the locked ref, loose or packed, or None if it does not exist yet.
+    pub fn current(&self) -> io::Result<Option<StoredRef>> {
+        match retrieve_ref_in(&self.geet_dir, &self.name) {
+            Ok(stored) => Ok(Some(stored)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the new contents of the ref and renames them into place, releasing the lock.
+    pub fn commit(mut self, data: &str) -> io::Result<()> {
+        let mut file = self.file.take().expect("ref lock is already released");
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.lock_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Deletes the ref, both its file and its packed entry, releasing the lock.
+    pub fn delete(self) -> io::Result<()> {
+        self.remove(true)
+    }
+
+    /// Removes the loose file of a ref whose value is also packed, releasing the lock.
+    pub fn remove_loose(self) -> io::Result<()> {
+        self.remove(false)
+    }
+
+    fn remove(self, unpack: bool) -> io::Result<()> {
+        let loose = match fs::remove_file(&self.path) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        let packed = unpack
+            && read_packed_refs_in(&self.geet_dir)?.contains_key(&self.name)
+            && update_packed_refs_in(&self.geet_dir, |refs| refs.remove(&self.name))?.is_some();
+        if !loose && !packed {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "ref not found"));
+        }
+        let path = self.path.clone();
+        let refs_dir = self.geet_dir.join("refs");
+        drop(self);
+
+        // remove the directories a nested name leaves empty, keeping the namespaces
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|d| d.parent().is_some_and(|p| p != refs_dir)) {
+            if !parent.starts_with(&refs_dir) || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        // after a commit the lock file has become the ref, so there is nothing left to remove
+        if !self.committed {
+            self.file.take();
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Lists the names of all refs below a directory of `./geet` such as `refs/heads`, recursively.
+pub fn list_ref_files(prefix: &str) -> io::Result<Vec<String>> {
+    list_ref_files_in(Path::new(GEET_DIR), prefix)
+}
+
+/// Lists the ref names below `prefix` in the `.geet` directory of any repository,
+/// loose and packed.
+pub fn list_ref_files_in(geet_dir: &Path, prefix: &str) -> io::Result<Vec<String>> {
+    Ok(list_stored_refs_in(geet_dir, prefix)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// Reads every ref below `prefix` in `./geet`, reading packed-refs only once.
+pub fn list_stored_refs(prefix: &str) -> io::Result<Vec<(String, StoredRef)>> {
+    list_stored_refs_in(Path::new(GEET_DIR), prefix)
+}
+
+/// Reads every ref below `prefix` in the `.geet` directory of any repository, sorted by name.
+/// A loose ref hides the packed entry of the same name.
+pub fn list_stored_refs_in(geet_dir: &Path, prefix: &str) -> io::Result<Vec<(String, StoredRef)>> {
+    let mut refs: BTreeMap<String, StoredRef> = read_packed_refs_in(geet_dir)?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&format!("{}/", prefix)))
+        .map(|(name, hash)| (name, StoredRef::Packed(hash)))
+        .collect();
+
+    let mut names = Vec::new();
+    let dir = geet_dir.join(prefix);
+    if dir.is_dir() {
+        collect_ref_files(&dir, prefix, &mut names)?;
+    }
+    for name in names {
+        if let Some(data) = read_loose_ref(&geet_dir.join(&name))? {
+            refs.insert(name, StoredRef::Loose(data));
+        }
+    }
+    Ok(refs.into_iter().collect())
+}
+
+fn collect_ref_files(dir: &Path, prefix: &str, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_ref_files(&entry.path(), &name, names)?;
+        } else if !name.ends_with(".lock") {
+            // a lock file is an update in progress, not a ref
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+/*
+every ref has a log of the values it had, one line per update, kept under `./geet/logs`
+with the same name as the ref: `logs/HEAD`, `logs/refs/heads/main`
+*/
+fn reflog_path(name: &str) -> PathBuf {
+    PathBuf::from(LOGS_DIR).join(name)
+}
+
+/// Appends a line to the reflog of a ref, creating the log on its first update.
+pub fn append_reflog(name: &str, line: &str) -> io::Result<()> {
+    append_reflog_in(Path::new(GEET_DIR), name, line)
+}
+
+/// Appends a line to the reflog of a ref in the `.geet` directory of any repository.
+pub fn append_reflog_in(geet_dir: &Path, name: &str, line: &str) -> io::Result<()> {
+    let path = geet_dir.join("logs").join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Reads the lines of a ref's reflog, oldest first; a ref that was never updated has none.
+pub fn read_reflog(name: &str) -> io::Result<Vec<String>> {
+    match fs::read_to_string(reflog_path(name)) {
+        Ok(data) => Ok(data.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Moves a reflog along with its renamed ref.
+pub fn rename_reflog(old_name: &str, new_name: &str) -> io::Result<()> {
+    let old_path = reflog_path(old_name);
+    if !old_path.is_file() {
+        return Ok(());
+    }
+    let new_path = reflog_path(new_name);
+    if let Some(parent) = new_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&old_path, &new_path)?;
+    remove_empty_dirs(old_path.parent(), Path::new(LOGS_DIR));
     Ok(())
 }
+
+/// Deletes the reflog of a deleted ref.
+pub fn delete_reflog(name: &str) -> io::Result<()> {
+    let path = reflog_path(name);
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            remove_empty_dirs(path.parent(), Path::new(LOGS_DIR));
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists the names of all refs that have a reflog.
+pub fn list_reflogs() -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let logs_dir = Path::new(LOGS_DIR);
+    if logs_dir.join("HEAD").is_file() {
+        names.push("HEAD".to_string());
+    }
+    if logs_dir.join("refs").is_dir() {
+        collect_ref_files(&logs_dir.join("refs"), "refs", &mut names)?;
+    }
+    Ok(names)
+}
+
+// remove the directories a nested name leaves empty, up to the given root
+fn remove_empty_dirs(mut dir: Option<&Path>, root: &Path) {
+    while let Some(parent) = dir.filter(|d| d.starts_with(root) && *d != root) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_refs_round_trip() {
+        let refs: BTreeMap<String, Hash> = [
+            ("refs/heads/main", "3b24d192d4047b56"),
+            ("refs/heads/feature/login", "670d893a8c1d57ea"),
+            ("refs/tags/v1.0", "c8181a4bbf1284ef"),
+        ]
+        .into_iter()
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect();
+
+        let packed = format_packed_refs(&refs);
+        assert!(packed.starts_with("# geet packed-refs\n"));
+        assert_eq!(parse_packed_refs(&packed).unwrap(), refs);
+        assert!(parse_packed_refs(&format_packed_refs(&BTreeMap::new()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let data = "# a comment\n\nabc123 refs/heads/main\n# another\n";
+        let refs = parse_packed_refs(data).unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs["refs/heads/main"], "abc123");
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let error = parse_packed_refs("abc123refs/heads/main\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -29,19 +29,20 @@ impl UserDataManager {
     }
 
     /* stores sensitive data by encrypting it before writing to a file (not needed in minimal prototype).*/
-    pub fn store_sensitive_data(&self, path: &str, data: &[u8]) -> Result<(), FileSystemError> {
+    pub fn store_sensitive_data(&self, _path: &str, _data: &[u8]) -> Result<(), FileSystemError> {
         // TODO: write encryption for sensitive data storage.
         todo!()
     }
 
     /* retrieves and decrypts sensitive data from a file (not needed in minimal prototype). */
-    pub fn retrieve_sensitive_data(&self, path: &str) -> Result<Vec<u8>, FileSystemError> {
+    pub fn retrieve_sensitive_data(&self, _path: &str) -> Result<Vec<u8>, FileSystemError> {
         // TODO: write decryption for retrieving sensitive data.
         todo!()
     }
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum FileSystemError {
     IoError(io::Error),
     EncryptionError,
@@ -1,9 +1,12 @@
+#![allow(dead_code)]
+
 mod behavior_hiding;
 mod file_hiding;
 mod repo_hiding;
 
 use behavior_hiding::cli_parser::parse_input;
-use std::{env, path::Path};
+use std::env;
+use std::process::ExitCode;
 
 /*const BASE_DIR: &str = ".";
 const GEET_DIR: &str = ".\\.geet";
@@ -14,11 +17,16 @@ const INDEX_FILE: &str = ".\\.geet\\index";*/
 const BASE_DIR: &str = ".";
 const GEET_DIR: &str = ".geet";
 const REFS_DIR: &str = ".geet/refs";
+const LOGS_DIR: &str = ".geet/logs";
 const OBJECTS_DIR: &str = ".geet/objects";
 const INDEX_FILE: &str = ".geet/index";
+const CONFIG_FILE: &str = ".geet/config";
+const MERGE_HEAD_FILE: &str = ".geet/MERGE_HEAD";
+const MERGE_MSG_FILE: &str = ".geet/MERGE_MSG";
+const EXCLUDE_FILE: &str = ".geet/info/exclude";
 
 
-fn main() {
+fn main() -> ExitCode {
     env::set_var("RUST_BACKTRACE", "1");
-    parse_input();
+    parse_input()
 }
@@ -1,4 +1,5 @@
 use super::Hash;
+use crate::file_hiding::file_log::{Object, ObjectKind};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
@@ -10,33 +11,68 @@ pub struct CommitMetadata {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "StoredCommit")]
 pub struct Commit {
-    pub tree_hash: Hash,           // Hash of the root tree the commit points to
-    pub parent_hash: Option<Hash>, // Hash of the parent commit
-    pub metadata: CommitMetadata,  // Commit metadata (author, message, timestamp)
+    pub tree_hash: Hash,          // Hash of the root tree the commit points to
+    pub parents: Vec<Hash>,       // Hashes of the parent commits, the first parent first
+    pub metadata: CommitMetadata, // Commit metadata (author, message, timestamp)
+}
+
+/* a commit as it is stored; commits made before merges existed have a single `parent_hash` */
+#[derive(Deserialize)]
+struct StoredCommit {
+    tree_hash: Hash,
+    #[serde(default)]
+    parents: Vec<Hash>,
+    #[serde(default)]
+    parent_hash: Option<Hash>,
+    metadata: CommitMetadata,
+}
+
+impl From<StoredCommit> for Commit {
+    fn from(stored: StoredCommit) -> Self {
+        let mut parents = stored.parents;
+        if parents.is_empty() {
+            parents.extend(stored.parent_hash);
+        }
+        Self {
+            tree_hash: stored.tree_hash,
+            parents,
+            metadata: stored.metadata,
+        }
+    }
 }
 
 impl Commit {
-    pub fn new_commit(
-        tree_hash: Hash,
-        parent_hash: Option<Hash>,
-        metadata: CommitMetadata,
-    ) -> Self {
+    pub fn new_commit(tree_hash: Hash, parents: Vec<Hash>, metadata: CommitMetadata) -> Self {
         Self {
             tree_hash,
-            parent_hash,
+            parents,
             metadata,
         }
     }
 
+    // the parent the commit was made on, None for a root commit
+    pub fn first_parent(&self) -> Option<&Hash> {
+        self.parents.first()
+    }
+
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
+    }
+
     // TODO (Optional): change it back to serialize to vectcor
     pub fn serialize(&self) -> String {
         serde_json::to_string(self)
-            .expect(format!("Failed to serialize commit {:#?}", self).as_str())
+            .unwrap_or_else(|_| panic!("Failed to serialize commit {:#?}", self))
     }
 
-    pub fn deserialize(data: &str) -> Self {
-        serde_json::from_str(data)
-            .expect(format!("Failed to deserialize commit: {}", data).as_str())
+    // reject anything that isn't a commit object instead of guessing from its content
+    pub fn deserialize(object: &Object) -> Result<Self, String> {
+        if object.kind != ObjectKind::Commit {
+            return Err(format!("expected a commit object, found a {}", object.kind));
+        }
+        serde_json::from_slice(&object.data)
+            .map_err(|e| format!("Failed to deserialize commit: {}", e))
     }
 }
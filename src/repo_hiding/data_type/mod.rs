@@ -16,6 +16,10 @@ mod reference;
 pub use reference::*;
 mod repo;
 pub use repo::*;
+mod tag;
+pub use tag::*;
+mod reflog;
+pub use reflog::*;
 
 /*
 Test Cases for Commit Serialization/Deserialization:
@@ -7,23 +7,70 @@ pub enum RefType {
     Branch,
     Tag,
     Head,
+    Remote,
 }
 
-/*  represents a branch or tag reference (not needed in minimal prototype). */
+impl RefType {
+    // the directory of `.geet` that refs of this kind live in
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            RefType::Branch => "refs/heads",
+            RefType::Tag => "refs/tags",
+            RefType::Head => "",
+            RefType::Remote => "refs/remotes",
+        }
+    }
+}
+
+/*  represents a branch, tag or remote-tracking reference, or HEAD.
+The name is short (`main`, `origin/main`); the ref type decides the namespace it is stored in.
+A symbolic ref (HEAD on a branch) names the full name of its target instead of holding a commit hash. */
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Ref {
     pub ref_type: RefType,         // Type of reference (branch or tag)
-    pub name: String,              // Branch or tag name
+    pub name: String,              // Branch, tag or remote-tracking name
     pub commit_hash: Option<Hash>, // Associated commit hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>, // Name of the ref this one follows, if it is symbolic
 }
 
 impl Ref {
     // TODO (Optional): change it back to serialize to vectcor
     pub fn serialize(&self) -> String {
-        serde_json::to_string(self).expect(format!("Failed to serialize ref {:#?}", self).as_str())
+        serde_json::to_string(self)
+            .unwrap_or_else(|_| panic!("Failed to serialize ref {:#?}", self))
     }
 
-    pub fn deserialize(data: &str) -> Self {
-        serde_json::from_str(data).expect(format!("Failed to deserialize ref: {}", data).as_str())
+    // the name the ref is stored under, e.g. `refs/heads/main`
+    pub fn full_name(&self) -> String {
+        full_ref_name(&self.ref_type, &self.name)
+    }
+
+    pub fn deserialize(data: &str) -> Result<Self, String> {
+        serde_json::from_str(data).map_err(|e| format!("Failed to deserialize ref: {}", e))
+    }
+}
+
+// the full name of a ref of the given kind, e.g. `refs/tags/v1.0` for the tag `v1.0`
+pub fn full_ref_name(ref_type: &RefType, name: &str) -> String {
+    match ref_type {
+        RefType::Head => name.to_string(),
+        _ => format!("{}/{}", ref_type.namespace(), name),
+    }
+}
+
+// the kind and short name of a full ref name, e.g. `(Tag, "v1.0")` for `refs/tags/v1.0`
+pub fn parse_full_ref_name(full_name: &str) -> Option<(RefType, String)> {
+    if full_name == "HEAD" {
+        return Some((RefType::Head, full_name.to_string()));
     }
+    [RefType::Branch, RefType::Tag, RefType::Remote]
+        .into_iter()
+        .find_map(|ref_type| {
+            let name = full_name
+                .strip_prefix(ref_type.namespace())?
+                .strip_prefix('/')?
+                .to_string();
+            Some((ref_type, name))
+        })
 }
@@ -0,0 +1,25 @@
+use super::Hash;
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/* one update of a ref, stored as a line of its reflog.
+A hash is None while the ref pointed to nothing, e.g. before it was created. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefLogEntry {
+    pub old_hash: Option<Hash>,
+    pub new_hash: Option<Hash>,
+    pub author: String, // Who moved the ref
+    pub timestamp: String,
+    pub reason: String, // What moved it, e.g. "commit: Fix typo" or "checkout: moving from main to dev"
+}
+
+impl RefLogEntry {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self)
+            .unwrap_or_else(|_| panic!("Failed to serialize reflog entry {:#?}", self))
+    }
+
+    pub fn deserialize(line: &str) -> Result<Self, String> {
+        serde_json::from_str(line).map_err(|e| format!("Failed to deserialize reflog entry: {}", e))
+    }
+}
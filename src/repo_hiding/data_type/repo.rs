@@ -1,21 +1,24 @@
+use crate::file_hiding::hasher_checker::HashAlgorithm;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-/* stores repository metadata (not needed in minimal prototype). */
+/* stores repository metadata, kept in .geet/config */
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RepositoryConfig {
     pub name: String,           // Repo name
     pub default_branch: String, // default branch name
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm, // older repositories have no config and use SHA-1
 }
 
 impl RepositoryConfig {
     pub fn serialize(&self) -> String {
         serde_json::to_string(self)
-            .expect(format!("Failed to serialize repository config {:#?}", self).as_str())
+            .unwrap_or_else(|_| panic!("Failed to serialize repository config {:#?}", self))
     }
 
     pub fn deserialize(data: &str) -> Self {
         serde_json::from_str(data)
-            .expect(format!("Failed to deserialize repository config: {}", data).as_str())
+            .unwrap_or_else(|_| panic!("Failed to deserialize repository config: {}", data))
     }
 }
@@ -0,0 +1,29 @@
+use super::Hash;
+use crate::file_hiding::file_log::{Object, ObjectKind};
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/* an annotated tag, stored as its own object; lightweight tags are just refs */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub target_hash: Hash, // Hash of the tagged commit
+    pub name: String,      // Tag name at the time it was created
+    pub tagger: String,
+    pub timestamp: String,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self)
+            .unwrap_or_else(|_| panic!("Failed to serialize tag {:#?}", self))
+    }
+
+    pub fn deserialize(object: &Object) -> Result<Self, String> {
+        if object.kind != ObjectKind::Tag {
+            return Err(format!("expected a tag object, found a {}", object.kind));
+        }
+        serde_json::from_slice(&object.data)
+            .map_err(|e| format!("Failed to deserialize tag: {}", e))
+    }
+}
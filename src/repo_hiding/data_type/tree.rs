@@ -1,4 +1,6 @@
 use super::Hash;
+use crate::file_hiding::file_log::{Object, ObjectKind};
+use crate::file_hiding::index::MODE_FILE;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
@@ -7,6 +9,8 @@ pub struct TreeNode {
     pub name: String, // Name of the file or directory
     pub hash: Hash,   // Hash of the tree node
     pub is_dir: bool, // Indicates if it's a directory or file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>, // File mode when it is not a regular file, e.g. 0o100755 for executables
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,6 +18,13 @@ pub struct Tree {
     pub nodes: Vec<TreeNode>, // List of nodes in the tree
 }
 
+impl TreeNode {
+    // the mode of a file node, regular unless the tree says otherwise
+    pub fn file_mode(&self) -> u32 {
+        self.mode.unwrap_or(MODE_FILE)
+    }
+}
+
 impl Tree {
     /* Creates a new, empty `Tree` instance. */
     pub fn new() -> Self {
@@ -22,15 +33,36 @@ impl Tree {
 
     /* Adds a directory node (Tree) to the `Tree`. */
     pub fn add_node(&mut self, name: String, hash: Hash, is_dir: bool) {
-        let node = TreeNode { name, hash, is_dir };
+        let node = TreeNode {
+            name,
+            hash,
+            is_dir,
+            mode: None,
+        };
+        self.nodes.push(node);
+    }
+
+    /* Adds a file node with the given mode; regular files store no mode. */
+    pub fn add_file(&mut self, name: String, hash: Hash, mode: u32) {
+        let node = TreeNode {
+            name,
+            hash,
+            is_dir: false,
+            mode: (mode != MODE_FILE).then_some(mode),
+        };
         self.nodes.push(node);
     }
 
     pub fn serialize(&self) -> String {
-        serde_json::to_string(self).expect(format!("Failed to serialize tree {:#?}", self).as_str())
+        serde_json::to_string(self)
+            .unwrap_or_else(|_| panic!("Failed to serialize tree {:#?}", self))
     }
 
-    pub fn deserialize(data: &str) -> Self {
-        serde_json::from_str(data).expect(format!("Failed to deserialize tree: {}", data).as_str())
+    pub fn deserialize(object: &Object) -> Result<Self, String> {
+        if object.kind != ObjectKind::Tree {
+            return Err(format!("expected a tree object, found a {}", object.kind));
+        }
+        serde_json::from_slice(&object.data)
+            .map_err(|e| format!("Failed to deserialize tree: {}", e))
     }
 }
@@ -1,83 +1,72 @@
-use crate::file_hiding::file_log::retrieve_object;
-use crate::file_hiding::ref_log::Hash;
-use crate::repo_hiding::data_type::{Commit, Tree};
-use crate::{BASE_DIR, GEET_DIR, OBJECTS_DIR};
-use std::path::Path;
-use std::{collections::HashMap, path::PathBuf};
+use crate::file_hiding::index::IndexEntry;
+use crate::repo_hiding::operation::revision::{get_revision, tree_entries};
+use std::collections::BTreeMap;
 
 pub struct Diff {
     pub deleted_files: Vec<String>,
     pub modified_files: Vec<(String, String, String)>,
     pub new_files: Vec<String>,
+    pub renamed_files: Vec<(String, String)>, // (old path, new path) of files moved unchanged
 }
 
-pub fn get_diffs(hash1: &String, hash2: &String) -> Diff {
-    let file_list1 = get_file_list(hash1).unwrap();
-    let file_list2 = get_file_list(hash2).unwrap();
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.deleted_files.is_empty()
+            && self.modified_files.is_empty()
+            && self.new_files.is_empty()
+            && self.renamed_files.is_empty()
+    }
+}
 
+// the changes from the commit `hash2` to the commit `hash1`
+pub fn get_diffs(hash1: &String, hash2: &String) -> Result<Diff, String> {
+    let file_list1 = tree_entries(&get_revision(hash1)?.tree_hash)?;
+    let file_list2 = tree_entries(&get_revision(hash2)?.tree_hash)?;
+    Ok(diff_entries(&file_list1, &file_list2))
+}
+
+/*
+compare two snapshots path by path: paths only in `new` are new, paths only in `old` are deleted,
+and paths whose blob or mode differ are modified, as (path, old hash, new hash).
+A deleted path whose blob shows up again under a new path is reported as renamed instead.
+*/
+pub fn diff_entries(
+    new: &BTreeMap<String, IndexEntry>,
+    old: &BTreeMap<String, IndexEntry>,
+) -> Diff {
     let mut deleted_files: Vec<String> = Vec::new();
     let mut modified_files: Vec<(String, String, String)> = Vec::new();
     let mut new_files: Vec<String> = Vec::new();
 
-    for (path, cur_hash) in &file_list1 {
-        if file_list2.contains_key(path) {
-            let parent_hash = file_list2.get(path).unwrap();
-            if parent_hash != cur_hash {
-                modified_files.push((
-                    path.to_string(),
-                    parent_hash.to_string(),
-                    cur_hash.to_string(),
-                ));
+    for (path, cur) in new {
+        match old.get(path) {
+            Some(parent) if !parent.same_contents(cur) => {
+                modified_files.push((path.clone(), parent.hash.clone(), cur.hash.clone()));
             }
-        } else {
-            new_files.push(path.to_string());
+            Some(_) => {}
+            None => new_files.push(path.clone()),
         }
     }
 
-    for (path, _) in &file_list2 {
-        if !file_list1.contains_key(path) {
-            deleted_files.push(path.to_string());
+    let mut renamed_files = Vec::new();
+    for path in old.keys() {
+        if new.contains_key(path) {
+            continue;
+        }
+        let hash = &old[path].hash;
+        match new_files
+            .iter()
+            .position(|new_path| new[new_path].hash == *hash)
+        {
+            Some(n) => renamed_files.push((path.clone(), new_files.remove(n))),
+            None => deleted_files.push(path.clone()),
         }
     }
 
-    return Diff {
+    Diff {
         deleted_files,
         modified_files,
         new_files,
-    };
-}
-
-fn get_file_list(hash: &Hash) -> Result<HashMap<String, String>, String> {
-    let serialized =
-        retrieve_object(&hash).map_err(|_| format!("commit with hash {} not found", hash))?;
-    let commit = Commit::deserialize(&serialized);
-    let mut file_list: HashMap<String, String> = HashMap::new();
-    get_file_list_helper(BASE_DIR, &commit.tree_hash, &mut file_list);
-    Ok(file_list)
-}
-
-fn get_file_list_helper(
-    dir: &str,
-    tree_hash: &String,
-    file_list: &mut HashMap<String, String>,
-) -> Result<(), String> {
-    let serialized = retrieve_object(tree_hash).map_err(|_| "Object with given hash not found")?;
-    let tree = Tree::deserialize(&serialized);
-
-    for node in tree.nodes {
-        let pathstr = PathBuf::from(dir).join(node.name);
-        let path = Path::new(&pathstr).to_str().unwrap();
-
-        if path.starts_with(GEET_DIR) {
-            continue;
-        }
-
-        if node.is_dir {
-            get_file_list_helper(&path, &node.hash, file_list);
-        } else {
-            file_list.insert(path.to_string(), node.hash);
-        }
+        renamed_files,
     }
-
-    Ok(())
 }
@@ -1,141 +1,711 @@
 pub mod diff;
-use super::revision::{apply_revision, get_revision};
-use crate::file_hiding::ref_log::{delete_ref as _delete_ref, retrieve_ref, store_ref};
-use crate::repo_hiding::data_type::{Commit, Hash, Ref, RefType};
-use crate::REFS_DIR;
-use std::collections::HashMap;
+pub mod reflog;
+pub mod tag;
+use super::revision::{apply_revision, get_revision, is_ancestor, peel_to_commit, CommitRange};
+use super::stage::{commit_entries, staged_diff, worktree_changes};
+use crate::file_hiding::hasher_checker::{repo_algorithm, HasherChecker};
+use crate::file_hiding::ref_log::{
+    delete_reflog, list_ref_files, list_stored_refs, ref_exists, rename_reflog, retrieve_ref,
+    update_packed_refs, RefLock, StoredRef,
+};
+use crate::repo_hiding::data_type::{
+    full_ref_name, parse_full_ref_name, Commit, Hash, Ref, RefType,
+};
+use crate::GEET_DIR;
+use chrono::DateTime;
+use reflog::record_ref_update;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
 
-// create a new ref with the given name
+/* the value a ref must still have for an update to go ahead */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpectedRef {
+    Any,      // overwrite whatever the ref holds
+    Absent,   // the ref must not exist yet
+    At(Hash), // the ref must still resolve to this commit
+}
+
+impl ExpectedRef {
+    // expect the commit a ref was read at; a ref that pointed to nothing may hold anything
+    pub fn from_read(hash: Option<Hash>) -> Self {
+        match hash {
+            Some(hash) => ExpectedRef::At(hash),
+            None => ExpectedRef::Any,
+        }
+    }
+}
+
+// create a new ref with the given short name in the namespace of its kind
 // hash is optional. If it is None, the ref will point to HEAD
-pub fn create_ref(ref_type: RefType, name: String, hash: Option<Hash>) -> Result<Ref, String> {
+// reason is recorded in the reflog, as for every other ref update
+pub fn create_ref(
+    ref_type: RefType,
+    name: String,
+    hash: Option<Hash>,
+    reason: &str,
+) -> Result<Ref, String> {
     let hash = if let Some(hash) = hash {
         Some(hash)
     } else {
         get_head()?
     };
+    if let Some(hash) = &hash {
+        check_hash(hash)?;
+    }
 
     let data = Ref {
         ref_type,
-        name: name.clone(),
+        name,
         commit_hash: hash,
+        target: None,
     };
-
-    let serialized = data.serialize();
-    store_ref(&name, &serialized).unwrap();
+    write_ref(&data, &ExpectedRef::Absent, reason)?;
     Ok(data)
 }
 
-// get the ref with the given name
+/*
+get the ref with the given name. Full names such as `refs/heads/main` are read as they are,
+short names are looked up the way revisions are: see resolve_ref_name().
+*/
 pub fn get_ref(name: &String) -> Result<Ref, String> {
-    let serialized = retrieve_ref(&name).map_err(|_| format!("Ref {} not found", name))?;
-    Ok(Ref::deserialize(&serialized))
+    let full_name = resolve_ref_name(name).ok_or_else(|| format!("Ref {} not found", name))?;
+    read_ref(&full_name)
 }
 
-// update the ref with the given name to point to the new hash
-pub fn update_ref(name: &String, new_hash: Hash) -> Result<Ref, String> {
+/*
+the full name of an existing ref, trying `HEAD` or a full `refs/...` name first and then
+a short name as a tag, a branch and a remote-tracking ref, in that order
+*/
+pub fn resolve_ref_name(name: &str) -> Option<String> {
+    // never let a name climb out of the refs directory
+    if name
+        .split('/')
+        .any(|part| part.is_empty() || part == "." || part == "..")
+    {
+        return None;
+    }
+
+    let mut candidates = Vec::new();
+    if name == "HEAD" || name.starts_with("refs/") {
+        candidates.push(name.to_string());
+    }
+    candidates.push(format!("refs/{}", name));
+    for ref_type in [RefType::Tag, RefType::Branch, RefType::Remote] {
+        candidates.push(full_ref_name(&ref_type, name));
+    }
+    candidates
+        .into_iter()
+        .find(|candidate| ref_exists(candidate))
+}
+
+fn read_ref(full_name: &str) -> Result<Ref, String> {
+    let stored = retrieve_ref(full_name).map_err(|_| format!("Ref {} not found", full_name))?;
+    parse_stored_ref(full_name, stored)
+}
+
+// a ref read from its own file, or rebuilt from its name and hash in packed-refs
+pub fn parse_stored_ref(full_name: &str, stored: StoredRef) -> Result<Ref, String> {
+    match stored {
+        StoredRef::Loose(serialized) => Ref::deserialize(&serialized)
+            .map_err(|e| format!("Ref {} is corrupt: {}", full_name, e)),
+        StoredRef::Packed(hash) => {
+            let (ref_type, name) = parse_full_ref_name(full_name)
+                .ok_or_else(|| format!("Packed ref {} has an unknown namespace", full_name))?;
+            Ok(Ref {
+                ref_type,
+                name,
+                commit_hash: Some(hash),
+                target: None,
+            })
+        }
+    }
+}
+
+// write a ref if it has the expected value and append the change to its reflog
+fn write_ref(data: &Ref, expected: &ExpectedRef, reason: &str) -> Result<(), String> {
+    let full_name = data.full_name();
+    let old_hash = swap_ref_in(Path::new(GEET_DIR), &full_name, Some(data), expected)?;
+    record_ref_update(&full_name, old_hash, resolved_hash(data), reason)
+}
+
+// delete a ref if it has the expected value, along with its reflog
+fn remove_ref(full_name: &str, expected: &ExpectedRef) -> Result<(), String> {
+    swap_ref_in(Path::new(GEET_DIR), full_name, None, expected)?;
+    delete_reflog(full_name).map_err(|e| format!("Failed to remove reflog of {}: {}", full_name, e))
+}
+
+/*
+replace a ref of the given `.geet` directory by `new`, or delete it when `new` is None, if it
+still has the expected value (compare-and-swap). The ref stays locked from the check until the
+new value is renamed into place, so of two processes updating the same ref the second one fails
+cleanly instead of overwriting the first. Returns the commit the ref resolved to before.
+*/
+pub fn swap_ref_in(
+    geet_dir: &Path,
+    full_name: &str,
+    new: Option<&Ref>,
+    expected: &ExpectedRef,
+) -> Result<Option<Hash>, String> {
+    let lock = RefLock::acquire_in(geet_dir, full_name)
+        .map_err(|e| format!("Failed to lock ref {}: {}", full_name, e))?;
+    let current = lock
+        .current()
+        .map_err(|e| format!("Failed to read ref {}: {}", full_name, e))?;
+    let exists = current.is_some();
+    // a corrupt ref can still be overwritten when any value is expected
+    let old_hash = current
+        .and_then(|stored| parse_stored_ref(full_name, stored).ok())
+        .and_then(|old| resolved_hash(&old));
+
+    match expected {
+        ExpectedRef::Absent if exists => {
+            return Err(format!("Ref {} already exists", full_name));
+        }
+        ExpectedRef::At(hash) if old_hash.as_ref() != Some(hash) => {
+            return Err(format!(
+                "Ref {} was changed by another process: expected {} but found {}",
+                full_name,
+                hash,
+                old_hash.as_deref().unwrap_or("nothing")
+            ));
+        }
+        _ => {}
+    }
+
+    match new {
+        Some(data) => lock.commit(&data.serialize()),
+        None => lock.delete(),
+    }
+    .map_err(|e| format!("Failed to write ref {}: {}", full_name, e))?;
+    Ok(old_hash)
+}
+
+// the commit a ref points to, following a symbolic ref; None when its target is missing
+fn resolved_hash(data: &Ref) -> Option<Hash> {
+    match &data.target {
+        Some(target) => get_ref(target).ok()?.commit_hash,
+        None => data.commit_hash.clone(),
+    }
+}
+
+// update the ref with the given name to point to the new hash, if it still has the expected value
+pub fn update_ref(
+    name: &String,
+    new_hash: Hash,
+    expected: &ExpectedRef,
+    reason: &str,
+) -> Result<Ref, String> {
+    check_hash(&new_hash)?;
     let mut data = get_ref(name)?;
     data.commit_hash = Some(new_hash.clone());
-
-    let serialized = data.serialize();
-    store_ref(&name, &serialized).unwrap();
+    write_ref(&data, expected, reason)?;
     Ok(data)
 }
 
-// rename the ref with the old name to the new name
-pub fn rename_ref(old_name: &String, new_name: &String) -> Result<Ref, String> {
-    let data = get_ref(old_name)?;
-    let serialized = data.serialize();
-    store_ref(&new_name, &serialized).unwrap();
-    delete_ref(old_name);
+// refs may only point to full hashes of the repository's algorithm
+fn check_hash(hash: &Hash) -> Result<(), String> {
+    if HasherChecker::is_valid_hash(hash) {
+        Ok(())
+    } else {
+        Err(format!("{} is not a valid {} hash", hash, repo_algorithm()))
+    }
+}
+
+// rename the ref with the old name to the new short name, keeping its kind and its reflog
+pub fn rename_ref(old_name: &String, new_name: &str) -> Result<Ref, String> {
+    let mut data = get_ref(old_name)?;
+    let old_full_name = data.full_name();
+    let expected = ExpectedRef::from_read(data.commit_hash.clone());
+    data.name = new_name.to_string();
+    if data.full_name() == old_full_name {
+        return Ok(data);
+    }
+
+    // the old file goes first, `a` may be renamed to `a/b`
+    swap_ref_in(Path::new(GEET_DIR), &old_full_name, None, &expected)?;
+    rename_reflog(&old_full_name, &data.full_name())
+        .map_err(|e| format!("Failed to move reflog of {}: {}", old_name, e))?;
+    let reason = format!("rename: {} to {}", old_full_name, data.full_name());
+    write_ref(&data, &ExpectedRef::Absent, &reason)?;
     Ok(data)
 }
 
-// delete the ref with the given name
-pub fn delete_ref(name: &String) -> Result<(), String> {
-    _delete_ref(name.as_str()).map_err(|_| format!("Ref {} not found", name))?;
+// delete the ref with the given name and its reflog, if it still has the expected value
+pub fn delete_ref(name: &String, expected: &ExpectedRef) -> Result<(), String> {
+    let full_name = resolve_ref_name(name).ok_or_else(|| format!("Ref {} not found", name))?;
+    remove_ref(&full_name, expected)
+}
+
+// create HEAD as a symbolic ref to the given branch
+pub fn create_head(branch: &str) -> Result<(), String> {
+    attach_head(branch, "init")
+}
+
+// the hash a ref points to, following a symbolic ref to its target
+pub fn get_ref_hash(name: &String) -> Result<Option<Hash>, String> {
+    let data = get_ref(name)?;
+    match data.target {
+        Some(target) => Ok(get_ref(&target)?.commit_hash),
+        None => Ok(data.commit_hash),
+    }
+}
+
+// get the hash that the HEAD is pointing to
+pub fn get_head() -> Result<Option<Hash>, String> {
+    get_ref_hash(&"HEAD".to_string())
+}
+
+// the short name of the branch HEAD refers to, or None when HEAD is detached
+pub fn current_branch() -> Result<Option<String>, String> {
+    let target = get_ref(&"HEAD".to_string())?.target;
+    Ok(target.map(|target| {
+        let prefix = format!("{}/", RefType::Branch.namespace());
+        target.strip_prefix(&prefix).unwrap_or(&target).to_string()
+    }))
+}
+
+// update the HEAD to point to the new hash; on a branch, this advances the branch
+// fails if HEAD no longer has the expected value, e.g. another commit was made meanwhile
+pub fn update_head(new_hash: &Hash, expected: &ExpectedRef, reason: &str) -> Result<(), String> {
+    match get_ref(&"HEAD".to_string())?.target {
+        Some(target) => {
+            let old_hash = get_head()?;
+            update_ref(&target, new_hash.clone(), expected, reason)?;
+            // HEAD moved along with its branch
+            record_ref_update("HEAD", old_hash, Some(new_hash.clone()), reason)?;
+        }
+        None => {
+            update_ref(&"HEAD".to_string(), new_hash.clone(), expected, reason)?;
+        }
+    };
     Ok(())
 }
 
-pub fn create_head() {
+// make HEAD follow the branch with the given short name
+pub fn attach_head(branch: &str, reason: &str) -> Result<(), String> {
+    store_head(Some(full_ref_name(&RefType::Branch, branch)), None, reason)
+}
+
+// point HEAD directly at a commit, leaving every branch where it is
+pub fn detach_head(commit_hash: &Hash, reason: &str) -> Result<(), String> {
+    check_hash(commit_hash)?;
+    store_head(None, Some(commit_hash.clone()), reason)
+}
+
+fn store_head(
+    target: Option<String>,
+    commit_hash: Option<Hash>,
+    reason: &str,
+) -> Result<(), String> {
     let data = Ref {
         ref_type: RefType::Head,
         name: "HEAD".to_string(),
-        commit_hash: None,
+        commit_hash,
+        target,
     };
+    write_ref(&data, &ExpectedRef::Any, reason)
+}
 
-    let serialized = data.serialize();
-    store_ref(&data.name, &serialized).unwrap();
+// apply the changes from the revision to the working directory and detach HEAD at it
+pub fn checkout_commit(commit_hash: &String) -> Result<(), String> {
+    checkout_detached(commit_hash, commit_hash)
 }
 
-// get the hash that the HEAD is pointing to
-pub fn get_head() -> Result<Option<Hash>, String> {
-    let head = get_ref(&"HEAD".to_string())?;
-    if let Some(hash) = head.commit_hash {
-        Ok(Some(hash))
-    } else {
-        Ok(None)
+fn checkout_detached(commit_hash: &Hash, label: &str) -> Result<(), String> {
+    let reason = checkout_reason(label)?;
+    if get_head()?.as_ref() != Some(commit_hash) {
+        ensure_clean_worktree(commit_hash)?;
+        apply_revision(commit_hash)?;
     }
+    detach_head(commit_hash, &reason)
 }
 
-// update the HEAD to point to the new hash
-pub fn update_head(new_hash: &Hash) {
-    update_ref(&"HEAD".to_string(), new_hash.clone());
+// describe a checkout for the reflog, e.g. "checkout: moving from main to v1.0"
+fn checkout_reason(to: &str) -> Result<String, String> {
+    let from = match current_branch()? {
+        Some(branch) => branch,
+        None => get_head()?.unwrap_or_default(),
+    };
+    Ok(format!("checkout: moving from {} to {}", from, to))
 }
 
-// apply the changes from the revision to the working directory
-pub fn checkout_commit(commit_hash: &String) -> Result<(), String> {
-    apply_revision(commit_hash)?;
-    update_head(commit_hash);
+// checkout the given ref by calling apply_revision(); HEAD follows a branch and detaches at anything else
+// local changes the checkout would overwrite make it fail before anything is touched
+pub fn checkout_ref(ref_name: &String) -> Result<(), String> {
+    let ref_data = get_ref(ref_name)?;
+    let hash = get_ref_hash(ref_name)?
+        .ok_or_else(|| format!("Ref {} does not point to any commit", ref_name))?;
+    if ref_data.ref_type != RefType::Branch {
+        return checkout_detached(&peel_to_commit(&hash)?, &ref_data.name);
+    }
+    let reason = checkout_reason(&ref_data.name)?;
+
+    // the working directory already matches when the branch is at the current commit
+    if get_head()? != Some(hash.clone()) {
+        ensure_clean_worktree(&hash)?;
+        apply_revision(&hash)?;
+    }
+    attach_head(&ref_data.name, &reason)
+}
+
+// create a branch at the given commit, refusing to overwrite an existing ref
+pub fn create_branch(name: &str, start: &Hash) -> Result<Ref, String> {
+    validate_ref_name(name, "branch")?;
+    check_ref_available(&RefType::Branch, name, None)?;
+    let reason = format!("branch: created from {}", start);
+    create_ref(
+        RefType::Branch,
+        name.to_string(),
+        Some(start.clone()),
+        &reason,
+    )
+}
+
+// rename a branch, keeping HEAD on it if it is the current branch
+pub fn rename_branch(old_name: &String, new_name: &str) -> Result<(), String> {
+    get_branch(old_name)?;
+    validate_ref_name(new_name, "branch")?;
+    check_ref_available(&RefType::Branch, new_name, Some(old_name))?;
+
+    let data = rename_ref(&full_ref_name(&RefType::Branch, old_name), new_name)?;
+    if current_branch()?.as_ref() == Some(old_name) {
+        let old_full_name = full_ref_name(&RefType::Branch, old_name);
+        let reason = format!("rename: {} to {}", old_full_name, data.full_name());
+        attach_head(new_name, &reason)?;
+    }
     Ok(())
 }
 
-// checkout the given ref by calling update_head() and apply_revision()
-pub fn checkout_ref(ref_name: &String) -> Result<(), String> {
-    let ref_data = get_ref(ref_name)?;
-    let hash = ref_data
-        .commit_hash
-        .expect(format!("Ref {} does not point to any commit", ref_name).as_str());
-    checkout_commit(&hash);
+// delete a branch; unless forced, only when its commits are already part of HEAD
+pub fn delete_branch(name: &String, force: bool) -> Result<(), String> {
+    let data = get_branch(name)?;
+    if current_branch()?.as_ref() == Some(name) {
+        return Err(format!(
+            "Cannot delete branch '{}' while it is checked out",
+            name
+        ));
+    }
+
+    if let (false, Some(tip), Some(head)) = (force, &data.commit_hash, get_head()?) {
+        if !is_ancestor(tip, &head)? {
+            return Err(format!(
+                "The branch '{}' is not fully merged. Use -D to delete it anyway.",
+                name
+            ));
+        }
+    }
+    remove_ref(&data.full_name(), &ExpectedRef::from_read(data.commit_hash))
+}
+
+// the branch with the given short name, ignoring tags and remote-tracking refs of that name
+pub fn get_branch(name: &String) -> Result<Ref, String> {
+    read_ref(&full_ref_name(&RefType::Branch, name))
+        .map_err(|_| format!("Branch {} not found", name))
+}
+
+/*
+refs are files, so a new ref can neither replace an existing one nor sit inside one:
+`feature` and `feature/login` cannot both be branches. `replacing` is left out of the check.
+*/
+pub fn check_ref_available(
+    ref_type: &RefType,
+    name: &str,
+    replacing: Option<&String>,
+) -> Result<(), String> {
+    let kind = match ref_type {
+        RefType::Tag => "tag",
+        _ => "branch",
+    };
+    let full_name = full_ref_name(ref_type, name);
+    let replaced = replacing.map(|old| full_ref_name(ref_type, old));
+    let existing =
+        list_ref_files(ref_type.namespace()).map_err(|e| format!("Failed to read refs: {}", e))?;
+    for other in existing
+        .iter()
+        .filter(|other| Some(*other) != replaced.as_ref())
+    {
+        if *other == full_name {
+            return Err(format!("A {} named '{}' already exists", kind, name));
+        }
+        if other.starts_with(&format!("{}/", full_name))
+            || full_name.starts_with(&format!("{}/", other))
+        {
+            return Err(format!(
+                "Cannot create {} '{}': it conflicts with the existing ref {}",
+                kind, name, other
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+branch and tag names become paths under .geet/refs, where each `/` starts a directory,
+and are parsed as revisions, so they must stay inside their namespace and avoid the
+characters revisions use
+*/
+pub fn validate_ref_name(name: &str, kind: &str) -> Result<(), String> {
+    let invalid = |reason: &str| {
+        Err(format!(
+            "'{}' is not a valid {} name: {}",
+            name, kind, reason
+        ))
+    };
+    if name.is_empty() {
+        return invalid("it is empty");
+    }
+    if name == "HEAD" || name == "@" {
+        return invalid("it is reserved");
+    }
+    if name.starts_with('-') {
+        return invalid("it cannot start with '-'");
+    }
+    if name.split('/').any(|part| part.is_empty()) {
+        return invalid("it cannot start or end with '/' or contain '//'");
+    }
+    if name.split('/').any(|part| part.starts_with('.')) {
+        return invalid("no part of it can start with '.'");
+    }
+    if name.contains("..") || name.contains("@{") {
+        return invalid("it cannot contain '..' or '@{'");
+    }
+    if name.split('/').any(|part| part.ends_with(".lock")) {
+        return invalid("no part of it can end with '.lock'");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || "\\~^:?*[".contains(*c))
+    {
+        return invalid(&format!("it cannot contain {:?}", c));
+    }
     Ok(())
 }
 
-// list all refs of the given kind (Branch, Tag, or Head)
+// check out a branch like checkout_ref(), which refuses to throw away uncommitted work
+pub fn switch_branch(name: &String) -> Result<(), String> {
+    checkout_ref(&get_branch(name)?.full_name())
+}
+
+/*
+fail before moving to the target commit if that would lose work: when there are staged changes,
+tracked files with local changes, or untracked files the target commit would overwrite
+(a file at the same path, inside a directory of it, or a directory where it has a file)
+*/
+pub fn ensure_clean_worktree(target: &Hash) -> Result<(), String> {
+    let staged = staged_diff()?;
+    let worktree = worktree_changes()?;
+    let mut changed: Vec<String> = staged
+        .new_files
+        .into_iter()
+        .chain(staged.deleted_files)
+        .chain(staged.modified_files.into_iter().map(|(path, _, _)| path))
+        .chain(
+            staged
+                .renamed_files
+                .into_iter()
+                .flat_map(|(old, new)| [old, new]),
+        )
+        .chain(worktree.modified)
+        .chain(worktree.deleted)
+        .collect();
+    if !changed.is_empty() {
+        changed.sort();
+        changed.dedup();
+        return Err(format!(
+            "Your local changes would be overwritten, commit them first:\n{}",
+            list_paths(&changed)
+        ));
+    }
+
+    let target = commit_entries(target)?;
+    let overwritten: Vec<String> = worktree
+        .untracked
+        .into_iter()
+        .filter(|path| {
+            target
+                .keys()
+                .any(|key| Path::new(path).starts_with(key) || Path::new(key).starts_with(path))
+        })
+        .collect();
+    if !overwritten.is_empty() {
+        return Err(format!(
+            "Untracked files would be overwritten, move or remove them first:\n{}",
+            list_paths(&overwritten)
+        ));
+    }
+    Ok(())
+}
+
+fn list_paths(paths: &[String]) -> String {
+    let paths: Vec<String> = paths.iter().map(|path| format!("  {}", path)).collect();
+    paths.join("\n")
+}
+
+// list all refs of the given kind (Branch, Tag, Remote or Head) by reading its namespace
 pub fn list_refs(kind: RefType) -> Result<Vec<Ref>, String> {
-    let mut ref_list = Vec::new();
-    let dir = std::fs::read_dir(REFS_DIR).unwrap();
-    for entry in dir {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        let name = path.file_name().unwrap().to_str().unwrap().to_string();
-        let data = get_ref(&name)?;
-        if data.ref_type == kind {
-            ref_list.push(data);
+    if kind == RefType::Head {
+        return Ok(vec![read_ref("HEAD")?]);
+    }
+    list_stored_refs(kind.namespace())
+        .map_err(|e| format!("Failed to read refs: {}", e))?
+        .into_iter()
+        .map(|(name, stored)| parse_stored_ref(&name, stored))
+        .collect()
+}
+
+// list every ref in the repository, whatever its kind
+pub fn list_all_refs() -> Result<Vec<Ref>, String> {
+    let mut refs = vec![read_ref("HEAD")?];
+    for (name, stored) in
+        list_stored_refs("refs").map_err(|e| format!("Failed to read refs: {}", e))?
+    {
+        refs.push(parse_stored_ref(&name, stored)?);
+    }
+    Ok(refs)
+}
+
+// list the full names of every ref without reading them, HEAD first
+pub fn list_ref_names() -> Result<Vec<String>, String> {
+    let mut names = vec!["HEAD".to_string()];
+    names.extend(list_ref_files("refs").map_err(|e| format!("Failed to read refs: {}", e))?);
+    Ok(names)
+}
+
+/*
+move every loose ref that points straight at an object into packed-refs and remove its file.
+All refs are packed first; each file is then removed under its own lock, and only if it
+still holds the value that was packed, so a ref updated in between keeps its newer loose value.
+Symbolic refs stay loose. Returns the number of refs that were packed.
+*/
+pub fn pack_refs() -> Result<usize, String> {
+    let mut loose = Vec::new();
+    for (name, stored) in
+        list_stored_refs("refs").map_err(|e| format!("Failed to read refs: {}", e))?
+    {
+        let StoredRef::Loose(serialized) = &stored else {
+            continue;
+        };
+        let data = parse_stored_ref(&name, stored.clone())?;
+        if let (Some(hash), None) = (data.commit_hash, data.target) {
+            loose.push((name, serialized.clone(), hash));
+        }
+    }
+    if loose.is_empty() {
+        return Ok(0);
+    }
+
+    update_packed_refs(|packed| {
+        for (name, _, hash) in &loose {
+            packed.insert(name.clone(), hash.clone());
         }
+    })
+    .map_err(|e| format!("Failed to write packed refs: {}", e))?;
+
+    let mut count = 0;
+    for (name, serialized, _) in &loose {
+        let lock =
+            RefLock::acquire(name).map_err(|e| format!("Failed to lock ref {}: {}", name, e))?;
+        let current = lock
+            .current()
+            .map_err(|e| format!("Failed to read ref {}: {}", name, e))?;
+        if current == Some(StoredRef::Loose(serialized.clone())) {
+            lock.remove_loose()
+                .map_err(|e| format!("Failed to remove ref {}: {}", name, e))?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/*
+repositories made before ref namespaces kept every ref, HEAD included, as a flat file in
+.geet/refs. Copy each one into the namespace of its kind, pointing HEAD at the full name of its
+branch, and only then delete the flat files, refs/HEAD last: a migration that fails or is
+interrupted leaves the flat refs in place and runs again with the next command.
+A flat ref named like a namespace, e.g. a branch `heads`, would be in the way of the refs/heads
+directory, so it is first moved aside to `refs/.flat-heads`, a name no ref can have.
+*/
+pub fn migrate_flat_refs() -> Result<(), String> {
+    if !ref_exists("refs/HEAD") {
+        return Ok(());
+    }
+    let geet_dir = Path::new(GEET_DIR);
+
+    for ref_type in [RefType::Branch, RefType::Tag, RefType::Remote] {
+        let name = ref_type.namespace();
+        if geet_dir.join(name).is_file() {
+            let data = read_ref(name)?;
+            let aside = name.replace("refs/", "refs/.flat-");
+            swap_ref_in(geet_dir, &aside, Some(&data), &ExpectedRef::Any)?;
+            swap_ref_in(geet_dir, name, None, &ExpectedRef::Any)?;
+        }
+    }
+
+    let names = list_ref_files("refs").map_err(|e| format!("Failed to read refs: {}", e))?;
+    let flat: Vec<&String> = names
+        .iter()
+        .filter(|name| name.matches('/').count() == 1)
+        .collect();
+    for name in &flat {
+        let mut data = read_ref(name)?;
+        if data.ref_type == RefType::Head {
+            data.name = "HEAD".to_string();
+            data.target = data
+                .target
+                .map(|branch| full_ref_name(&RefType::Branch, &branch));
+        }
+        // a ref already copied by an interrupted migration is left as it is
+        let full_name = data.full_name();
+        let copied = read_ref(&full_name).is_ok_and(|old| old.serialize() == data.serialize());
+        if !copied {
+            swap_ref_in(geet_dir, &full_name, Some(&data), &ExpectedRef::Absent)?;
+        }
+    }
+
+    for name in flat.iter().filter(|name| name.as_str() != "refs/HEAD") {
+        swap_ref_in(geet_dir, name, None, &ExpectedRef::Any)?;
     }
-    Ok(ref_list)
+    swap_ref_in(geet_dir, "refs/HEAD", None, &ExpectedRef::Any)?;
+    Ok(())
 }
 
-// list all commits of the given ref
+// list the commits of the given range, newest first, following every parent of merge commits
 // count is optional. If it is None, all commits will be listed
 pub fn list_commits(
-    ref_name: String,
+    range: &CommitRange,
     count: Option<i32>,
-) -> Result<HashMap<String, Commit>, String> {
-    let cur_ref = get_ref(&ref_name)?;
-    let mut cur_commit_hash = cur_ref
-        .commit_hash
-        .ok_or_else(|| "There isn't any commits yet".to_string())?;
-
-    let mut commit_map = HashMap::new();
-    for _ in 0..count.unwrap_or(i32::MAX) {
-        let commit = get_revision(&cur_commit_hash)?;
-        commit_map.insert(cur_commit_hash.clone(), commit.clone());
+) -> Result<Vec<(Hash, Commit)>, String> {
+    // everything reachable from the start of the range is left out
+    let mut excluded = HashSet::new();
+    let mut pending: Vec<Hash> = range.from.iter().cloned().collect();
+    while let Some(hash) = pending.pop() {
+        if excluded.insert(hash.clone()) {
+            pending.extend(get_revision(&hash)?.parents);
+        }
+    }
 
-        match commit.parent_hash {
-            Some(hash) => cur_commit_hash = hash,
-            None => break,
+    // always continue with the newest commit seen so far, so that both sides of a merge interleave
+    let mut commits = Vec::new();
+    let mut queue = BinaryHeap::new();
+    let mut loaded: HashMap<Hash, Commit> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut found = vec![range.to.clone()];
+    while commits.len() < count.unwrap_or(i32::MAX) as usize {
+        for hash in found.drain(..) {
+            if !excluded.contains(&hash) && seen.insert(hash.clone()) {
+                let commit = get_revision(&hash)?;
+                let time = DateTime::parse_from_rfc3339(&commit.metadata.timestamp).ok();
+                queue.push((time, hash.clone()));
+                loaded.insert(hash, commit);
+            }
         }
+        let Some((_, hash)) = queue.pop() else {
+            break;
+        };
+        let commit = loaded.remove(&hash).expect("queued commits are loaded");
+        found.extend(commit.parents.iter().cloned());
+        commits.push((hash, commit));
     }
 
-    Ok(commit_map)
+    Ok(commits)
 }
@@ -0,0 +1,96 @@
+use super::resolve_ref_name;
+use crate::file_hiding::ref_log::{
+    append_reflog, append_reflog_in, list_reflogs, read_reflog as _read_reflog,
+};
+use crate::repo_hiding::data_type::{Hash, RefLogEntry};
+use std::collections::HashSet;
+use std::path::Path;
+
+// who moves refs: GEET_AUTHOR if it is set, otherwise the user geet runs as
+pub fn reflog_author() -> String {
+    ["GEET_AUTHOR", "USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "Anonymous".to_string())
+}
+
+// append an update to the reflog of the ref with the given full name
+pub fn record_ref_update(
+    full_name: &str,
+    old_hash: Option<Hash>,
+    new_hash: Option<Hash>,
+    reason: &str,
+) -> Result<(), String> {
+    let entry = new_entry(old_hash, new_hash, reason);
+    append_reflog(full_name, &entry.serialize())
+        .map_err(|e| format!("Failed to write reflog of {}: {}", full_name, e))
+}
+
+// append an update to a reflog in another repository, e.g. for a branch moved by push
+pub fn record_ref_update_in(
+    geet_dir: &Path,
+    full_name: &str,
+    old_hash: Option<Hash>,
+    new_hash: Option<Hash>,
+    reason: &str,
+) -> Result<(), String> {
+    let entry = new_entry(old_hash, new_hash, reason);
+    append_reflog_in(geet_dir, full_name, &entry.serialize())
+        .map_err(|e| format!("Failed to write reflog of {}: {}", full_name, e))
+}
+
+fn new_entry(old_hash: Option<Hash>, new_hash: Option<Hash>, reason: &str) -> RefLogEntry {
+    RefLogEntry {
+        old_hash,
+        new_hash,
+        author: reflog_author(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        reason: reason.to_string(),
+    }
+}
+
+// the full name of a ref and its reflog, newest first, so that entry n is what `ref@{n}` names
+pub fn read_reflog(name: &str) -> Result<(String, Vec<RefLogEntry>), String> {
+    let full_name = resolve_ref_name(name).ok_or_else(|| format!("Ref {} not found", name))?;
+    let entries = parse_reflog(&full_name)?;
+    Ok((full_name, entries))
+}
+
+fn parse_reflog(full_name: &str) -> Result<Vec<RefLogEntry>, String> {
+    let lines = _read_reflog(full_name)
+        .map_err(|e| format!("Failed to read reflog of {}: {}", full_name, e))?;
+    lines
+        .iter()
+        .rev()
+        .map(|line| RefLogEntry::deserialize(line))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Reflog of {} is corrupt: {}", full_name, e))
+}
+
+// the value a ref had n updates ago; `ref@{0}` is its current value
+pub fn reflog_entry(name: &str, n: usize) -> Result<Hash, String> {
+    let (_, entries) = read_reflog(name)?;
+    if entries.is_empty() {
+        return Err(format!("No reflog is recorded for {}", name));
+    }
+    let entry = entries
+        .get(n)
+        .ok_or_else(|| format!("The reflog of {} only has {} entries", name, entries.len()))?;
+    entry
+        .new_hash
+        .clone()
+        .ok_or_else(|| format!("{}@{{{}}} does not point to any commit", name, n))
+}
+
+// every hash recorded in any reflog; gc keeps them so that the reflog can still recover them
+pub fn reflog_hashes() -> Result<HashSet<Hash>, String> {
+    let mut hashes = HashSet::new();
+    let names = list_reflogs().map_err(|e| format!("Failed to read reflogs: {}", e))?;
+    for name in names {
+        for entry in parse_reflog(&name)? {
+            hashes.extend(entry.old_hash);
+            hashes.extend(entry.new_hash);
+        }
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,65 @@
+use super::{check_ref_available, create_ref, delete_ref, get_ref, validate_ref_name, ExpectedRef};
+use crate::file_hiding::file_log::{retrieve_object, store_object, ObjectKind};
+use crate::repo_hiding::data_type::{full_ref_name, Hash, Ref, RefType, Tag};
+
+// create a lightweight tag: a ref that points straight at the commit
+pub fn create_lightweight_tag(name: &str, commit_hash: &Hash) -> Result<Ref, String> {
+    check_new_tag(name)?;
+    let reason = format!("tag: created at {}", commit_hash);
+    create_ref(
+        RefType::Tag,
+        name.to_string(),
+        Some(commit_hash.clone()),
+        &reason,
+    )
+}
+
+// create an annotated tag: a tag object with tagger, date and message, and a ref pointing at it
+pub fn create_annotated_tag(
+    name: &str,
+    commit_hash: &Hash,
+    tagger: &str,
+    message: &str,
+) -> Result<Ref, String> {
+    check_new_tag(name)?;
+
+    let tag = Tag {
+        target_hash: commit_hash.clone(),
+        name: name.to_string(),
+        tagger: tagger.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        message: message.to_string(),
+    };
+    let tag_hash = store_object(ObjectKind::Tag, tag.serialize().as_bytes())
+        .map_err(|e| format!("Failed to store tag: {}", e))?;
+    let reason = format!("tag: created at {}", commit_hash);
+    create_ref(RefType::Tag, name.to_string(), Some(tag_hash), &reason)
+}
+
+pub fn delete_tag(name: &str) -> Result<(), String> {
+    let data = get_tag(name)?;
+    delete_ref(&data.full_name(), &ExpectedRef::from_read(data.commit_hash))
+}
+
+// the tag with the given short name, ignoring branches of that name
+pub fn get_tag(name: &str) -> Result<Ref, String> {
+    get_ref(&full_ref_name(&RefType::Tag, name)).map_err(|_| format!("Tag {} not found", name))
+}
+
+// the tag object behind an annotated tag ref, or None for a lightweight tag
+pub fn get_tag_object(data: &Ref) -> Result<Option<Tag>, String> {
+    let Some(hash) = &data.commit_hash else {
+        return Ok(None);
+    };
+    let object = retrieve_object(hash).map_err(|_| format!("object {} not found", hash))?;
+    if object.kind == ObjectKind::Tag {
+        Ok(Some(Tag::deserialize(&object)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn check_new_tag(name: &str) -> Result<(), String> {
+    validate_ref_name(name, "tag")?;
+    check_ref_available(&RefType::Tag, name, None)
+}
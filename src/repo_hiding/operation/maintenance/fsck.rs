@@ -0,0 +1,243 @@
+use crate::file_hiding::file_log::{list_objects, retrieve_object, verify_object, ObjectKind};
+use crate::file_hiding::merge_state::read_merge_head;
+use crate::repo_hiding::data_type::{Commit, Hash, RefType, Tag, Tree};
+use crate::repo_hiding::operation::branch::reflog::reflog_hashes;
+use crate::repo_hiding::operation::branch::{get_ref, list_ref_names};
+use crate::repo_hiding::operation::stage::index_hashes;
+use crate::OBJECTS_DIR;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/* a single problem found by fsck */
+#[derive(Serialize, Debug, Clone)]
+pub struct FsckIssue {
+    pub name: String,         // object hash, or ref name for bad refs
+    pub kind: Option<String>, // object kind when it is known
+    pub detail: String,
+}
+
+/* everything fsck found, serializable for machine-readable output */
+#[derive(Serialize, Debug, Default)]
+pub struct FsckReport {
+    pub checked_objects: usize,
+    pub checked_refs: usize,
+    pub missing: Vec<FsckIssue>,
+    pub corrupt: Vec<FsckIssue>,
+    pub bad_refs: Vec<FsckIssue>,
+    pub dangling: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    // dangling objects are harmless, everything else means the repository is damaged
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty() && self.bad_refs.is_empty()
+    }
+}
+
+enum Parsed {
+    Commit(Commit),
+    Tree(Tree),
+    Tag(Tag),
+    Other,
+}
+
+/*
+check the whole repository:
+1. every object decompresses, parses, and re-hashes to the name it is stored under
+2. every commit's tree and parents, and every tree node, resolves to an object of the right kind
+3. every ref points to an existing commit (or annotated tag)
+objects that nothing refers to are reported as dangling.
+*/
+pub fn fsck_repo() -> Result<FsckReport, String> {
+    let mut report = FsckReport::default();
+    let hashes = list_objects(Path::new(OBJECTS_DIR)).map_err(|e| e.to_string())?;
+
+    // pass 1: read and verify each object on its own
+    let mut objects: BTreeMap<Hash, (ObjectKind, Parsed)> = BTreeMap::new();
+    let mut sorted_hashes: Vec<&Hash> = hashes.iter().collect();
+    sorted_hashes.sort();
+    for hash in sorted_hashes {
+        report.checked_objects += 1;
+        let corrupt = |kind: Option<ObjectKind>, detail: String| FsckIssue {
+            name: hash.clone(),
+            kind: kind.map(|k| k.to_string()),
+            detail,
+        };
+
+        let object = match retrieve_object(hash) {
+            Ok(object) => object,
+            Err(e) => {
+                report
+                    .corrupt
+                    .push(corrupt(None, format!("unreadable: {}", e)));
+                continue;
+            }
+        };
+        if !verify_object(hash, &object) {
+            report
+                .corrupt
+                .push(corrupt(Some(object.kind), "hash mismatch".to_string()));
+            continue;
+        }
+
+        let parsed = match object.kind {
+            ObjectKind::Commit => Commit::deserialize(&object).map(Parsed::Commit),
+            ObjectKind::Tree => Tree::deserialize(&object).map(Parsed::Tree),
+            ObjectKind::Tag => Tag::deserialize(&object).map(Parsed::Tag),
+            ObjectKind::Blob => Ok(Parsed::Other),
+        };
+        match parsed {
+            Ok(parsed) => {
+                objects.insert(hash.clone(), (object.kind, parsed));
+            }
+            Err(e) => report.corrupt.push(corrupt(Some(object.kind), e)),
+        }
+    }
+
+    // pass 2: follow the links between objects
+    let mut referenced: HashSet<Hash> = HashSet::new();
+    let mut missing: HashSet<Hash> = HashSet::new(); // the hashes of `report.missing`
+    let mut links: Vec<(Hash, ObjectKind, &Hash, ObjectKind)> = Vec::new();
+    for (hash, (kind, parsed)) in &objects {
+        match parsed {
+            Parsed::Commit(commit) => {
+                links.push((hash.clone(), *kind, &commit.tree_hash, ObjectKind::Tree));
+                for parent_hash in &commit.parents {
+                    links.push((hash.clone(), *kind, parent_hash, ObjectKind::Commit));
+                }
+            }
+            Parsed::Tree(tree) => {
+                for node in &tree.nodes {
+                    let expected = if node.is_dir {
+                        ObjectKind::Tree
+                    } else {
+                        ObjectKind::Blob
+                    };
+                    links.push((hash.clone(), *kind, &node.hash, expected));
+                }
+            }
+            Parsed::Tag(tag) => {
+                links.push((hash.clone(), *kind, &tag.target_hash, ObjectKind::Commit));
+            }
+            Parsed::Other => {}
+        }
+    }
+    for (from, from_kind, to, expected) in links {
+        referenced.insert(to.clone());
+        check_link(
+            &objects,
+            &hashes,
+            to,
+            expected,
+            &mut report,
+            &mut missing,
+            || format!("referenced by {} {}", from_kind, from),
+        );
+    }
+
+    // pass 3: every ref must point to a commit, or a tag object for tags
+    for name in list_ref_names()? {
+        report.checked_refs += 1;
+        let bad_ref = |detail: String| FsckIssue {
+            name: name.clone(),
+            kind: None,
+            detail,
+        };
+
+        let data = match get_ref(&name) {
+            Ok(data) => data,
+            Err(e) => {
+                report.bad_refs.push(bad_ref(e));
+                continue;
+            }
+        };
+        if let Some(target) = &data.target {
+            if let Err(e) = get_ref(target) {
+                report
+                    .bad_refs
+                    .push(bad_ref(format!("follows a broken ref: {}", e)));
+            }
+            continue;
+        }
+        let Some(commit_hash) = data.commit_hash else {
+            continue;
+        };
+
+        referenced.insert(commit_hash.clone());
+        match objects.get(&commit_hash) {
+            Some((ObjectKind::Commit, _)) => {}
+            Some((ObjectKind::Tag, _)) if data.ref_type == RefType::Tag => {}
+            Some((kind, _)) => report.bad_refs.push(bad_ref(format!(
+                "points to {} {} instead of a commit",
+                kind, commit_hash
+            ))),
+            None if hashes.contains(&commit_hash) => report
+                .bad_refs
+                .push(bad_ref(format!("points to corrupt object {}", commit_hash))),
+            None => report
+                .bad_refs
+                .push(bad_ref(format!("points to missing commit {}", commit_hash))),
+        }
+    }
+
+    // objects a reflog can still bring back are not dangling either
+    referenced.extend(reflog_hashes()?);
+    // and neither are the blobs staged for the next commit
+    referenced.extend(index_hashes()?);
+    // or the commit of a merge in progress, which `merge --continue` makes a parent
+    let merge_head = read_merge_head().map_err(|e| format!("Failed to read MERGE_HEAD: {}", e))?;
+    if let Some(merge_head) = &merge_head {
+        referenced.insert(merge_head.clone());
+        check_link(
+            &objects,
+            &hashes,
+            merge_head,
+            ObjectKind::Commit,
+            &mut report,
+            &mut missing,
+            || "referenced by MERGE_HEAD".to_string(),
+        );
+    }
+
+    // objects nothing points to
+    for (hash, (kind, _)) in &objects {
+        if !referenced.contains(hash) {
+            report.dangling.push(FsckIssue {
+                name: hash.clone(),
+                kind: Some(kind.to_string()),
+                detail: "not referenced by any ref or object".to_string(),
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+// record a missing or mistyped target of a link; corrupt targets were already reported in pass 1
+fn check_link(
+    objects: &BTreeMap<Hash, (ObjectKind, Parsed)>,
+    hashes: &HashSet<Hash>,
+    target: &Hash,
+    expected: ObjectKind,
+    report: &mut FsckReport,
+    missing: &mut HashSet<Hash>,
+    source: impl Fn() -> String,
+) {
+    match objects.get(target) {
+        Some((kind, _)) if *kind == expected => {}
+        Some((kind, _)) => report.corrupt.push(FsckIssue {
+            name: target.clone(),
+            kind: Some(kind.to_string()),
+            detail: format!("expected a {}, {}", expected, source()),
+        }),
+        None if hashes.contains(target) => {}
+        // an object is reported missing once, by the first object found linking to it
+        None if !missing.insert(target.clone()) => {}
+        None => report.missing.push(FsckIssue {
+            name: target.clone(),
+            kind: Some(expected.to_string()),
+            detail: source(),
+        }),
+    }
+}
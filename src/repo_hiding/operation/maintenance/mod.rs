@@ -0,0 +1,169 @@
+pub mod fsck;
+
+use super::branch::reflog::reflog_hashes;
+use super::branch::{list_all_refs, pack_refs};
+use super::stage::index_hashes;
+use crate::file_hiding::file_log::{
+    delete_loose_object, does_object_exist, list_loose_objects, loose_object_time,
+    restore_loose_object, retrieve_object, set_loose_object_time, ObjectKind,
+};
+use crate::file_hiding::merge_state::read_merge_head;
+use crate::file_hiding::pack::{
+    packed_object_times, read_packed_object, repack, repack_plan, PackStats,
+};
+use crate::repo_hiding::data_type::{Commit, Hash, Tag, Tree};
+use crate::OBJECTS_DIR;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/* what a garbage collection did, or would do in a dry run */
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub pack: PackStats,
+    pub pruned: Vec<(Hash, ObjectKind)>, // unreachable objects past the grace period
+    pub kept_recent: usize,              // unreachable objects still inside the grace period
+    pub packed_refs: usize,              // loose refs moved into packed-refs
+}
+
+// pack every loose object and existing pack into a single pack file
+pub fn repack_repo() -> Result<PackStats, String> {
+    repack(Path::new(OBJECTS_DIR), None).map_err(|e| format!("Failed to repack objects: {}", e))
+}
+
+/*
+walk every ref and reflog entry and collect the tags, commits, trees and blobs reachable from it,
+along with the blobs staged in the index and the commit of a merge in progress.
+Reflog entries whose objects are already gone are skipped.
+Fails if a reachable commit or tree is missing, since pruning on a partial walk
+could delete objects that are still needed.
+*/
+pub fn reachable_objects() -> Result<HashSet<Hash>, String> {
+    let mut reachable = index_hashes()?;
+    let mut commits: Vec<Hash> = list_all_refs()?
+        .into_iter()
+        .filter_map(|data| data.commit_hash)
+        .collect();
+    commits.extend(
+        reflog_hashes()?
+            .into_iter()
+            .filter(|hash| does_object_exist(hash)),
+    );
+    commits.extend(read_merge_head().map_err(|e| format!("Failed to read MERGE_HEAD: {}", e))?);
+
+    while let Some(commit_hash) = commits.pop() {
+        if !reachable.insert(commit_hash.clone()) {
+            continue;
+        }
+
+        // annotated tags are kept along with the commit they point to
+        let object = retrieve_object(&commit_hash)
+            .map_err(|_| format!("commit with hash {} not found", commit_hash))?;
+        if object.kind == ObjectKind::Tag {
+            commits.push(Tag::deserialize(&object)?.target_hash);
+            continue;
+        }
+
+        let commit = Commit::deserialize(&object).map_err(|e| format!("{}: {}", commit_hash, e))?;
+        mark_tree(&commit.tree_hash, &mut reachable)?;
+        commits.extend(commit.parents);
+    }
+
+    Ok(reachable)
+}
+
+fn mark_tree(tree_hash: &Hash, reachable: &mut HashSet<Hash>) -> Result<(), String> {
+    if !reachable.insert(tree_hash.clone()) {
+        return Ok(());
+    }
+
+    let object = retrieve_object(tree_hash)
+        .map_err(|_| format!("tree with hash {} not found", tree_hash))?;
+    let tree = Tree::deserialize(&object).map_err(|e| format!("{}: {}", tree_hash, e))?;
+    for node in tree.nodes {
+        if node.is_dir {
+            mark_tree(&node.hash, reachable)?;
+        } else {
+            reachable.insert(node.hash);
+        }
+    }
+    Ok(())
+}
+
+/*
+pack the refs and objects of the repository and, with `prune`, delete objects that no ref can reach.
+Unreachable objects younger than `grace` are kept as loose objects so that work
+in progress (e.g. a tree stored by a commit that is still being made) survives.
+*/
+pub fn gc_repo(prune: bool, dry_run: bool, grace: Duration) -> Result<GcReport, String> {
+    let mut report = GcReport::default();
+    if !dry_run {
+        report.packed_refs = pack_refs()?;
+    }
+    let objects_dir = Path::new(OBJECTS_DIR);
+    if !prune {
+        report.pack = if dry_run {
+            repack_plan(objects_dir, None).map_err(|e| e.to_string())?
+        } else {
+            repack_repo()?
+        };
+        return Ok(report);
+    }
+
+    let reachable = reachable_objects()?;
+    let now = SystemTime::now();
+    let is_expired = |time: SystemTime| now.duration_since(time).unwrap_or_default() >= grace;
+
+    let loose = list_loose_objects(objects_dir).map_err(|e| e.to_string())?;
+    let packed = packed_object_times(objects_dir).map_err(|e| e.to_string())?;
+
+    let mut expired_loose = Vec::new();
+    let mut recent_packed = Vec::new();
+    for hash in loose.iter().filter(|hash| !reachable.contains(*hash)) {
+        let time = loose_object_time(objects_dir, hash).map_err(|e| e.to_string())?;
+        if is_expired(time) {
+            expired_loose.push(hash.clone());
+        } else {
+            report.kept_recent += 1;
+        }
+    }
+    for (hash, time) in &packed {
+        if reachable.contains(hash) || loose.contains(hash) {
+            continue;
+        }
+        if is_expired(*time) {
+            let kind = retrieve_object(hash).map_err(|e| e.to_string())?.kind;
+            report.pruned.push((hash.clone(), kind));
+        } else {
+            recent_packed.push((hash.clone(), *time));
+            report.kept_recent += 1;
+        }
+    }
+    for hash in &expired_loose {
+        let kind = retrieve_object(hash).map_err(|e| e.to_string())?.kind;
+        report.pruned.push((hash.clone(), kind));
+    }
+    report.pruned.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if dry_run {
+        report.pack = repack_plan(objects_dir, Some(&reachable)).map_err(|e| e.to_string())?;
+        return Ok(report);
+    }
+
+    // unreachable packed objects inside the grace period move out of the pack as loose objects,
+    // keeping the age of their pack so they still expire on time
+    for (hash, time) in &recent_packed {
+        let object = read_packed_object(objects_dir, hash)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("packed object {} not found", hash))?;
+        restore_loose_object(objects_dir, hash, &object).map_err(|e| e.to_string())?;
+        set_loose_object_time(objects_dir, hash, *time).map_err(|e| e.to_string())?;
+    }
+    for hash in &expired_loose {
+        delete_loose_object(objects_dir, hash).map_err(|e| e.to_string())?;
+    }
+
+    report.pack = repack(objects_dir, Some(&reachable))
+        .map_err(|e| format!("Failed to repack objects: {}", e))?;
+    Ok(report)
+}
@@ -0,0 +1,222 @@
+/* the result of merging the lines of three versions of a file */
+pub struct LineMerge {
+    pub data: Vec<u8>, // merged contents, with conflict markers around conflicting hunks
+    pub conflicts: usize, // number of conflicting hunks
+}
+
+// above this many line pairs the common subsequence is not computed and the whole
+// differing middle of the files is treated as changed
+const MAX_TABLE_SIZE: usize = 16 * 1024 * 1024;
+
+/*
+three-way merge of `ours` and `theirs` against their common `base`, line by line.
+Both sides are matched against the base; lines that are unchanged on both sides split the files
+into hunks. A hunk changed on one side only takes that side, a hunk changed the same way on both
+sides is taken once, and anything else is a conflict written between `<<<<<<<`, `=======` and
+`>>>>>>>` markers labelled with `ours_label` and `theirs_label`.
+*/
+pub fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> LineMerge {
+    let base = split_lines(base);
+    let ours = split_lines(ours);
+    let theirs = split_lines(theirs);
+    let to_ours = match_lines(&base, &ours);
+    let to_theirs = match_lines(&base, &theirs);
+
+    let mut merge = LineMerge {
+        data: Vec::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // a base line kept at the current position of both sides is stable
+        if i < base.len() && to_ours[i] == Some(j) && to_theirs[i] == Some(k) {
+            merge.data.extend_from_slice(base[i]);
+            (i, j, k) = (i + 1, j + 1, k + 1);
+            continue;
+        }
+
+        // otherwise the hunk runs up to the next base line both sides kept
+        let next = (i..base.len()).find(|&n| to_ours[n].is_some() && to_theirs[n].is_some());
+        let (end_i, end_j, end_k) = match next {
+            Some(n) => (n, to_ours[n].unwrap(), to_theirs[n].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        merge_hunk(
+            &mut merge,
+            &base[i..end_i],
+            &ours[j..end_j],
+            &theirs[k..end_k],
+            ours_label,
+            theirs_label,
+        );
+        (i, j, k) = (end_i, end_j, end_k);
+
+        if next.is_none() {
+            return merge;
+        }
+    }
+}
+
+fn merge_hunk(
+    merge: &mut LineMerge,
+    base: &[&[u8]],
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    ours_label: &str,
+    theirs_label: &str,
+) {
+    let lines = if ours == base || ours == theirs {
+        theirs
+    } else if theirs == base {
+        ours
+    } else {
+        merge.conflicts += 1;
+        merge
+            .data
+            .extend_from_slice(format!("<<<<<<< {}\n", ours_label).as_bytes());
+        push_lines(&mut merge.data, ours);
+        merge.data.extend_from_slice(b"=======\n");
+        push_lines(&mut merge.data, theirs);
+        merge
+            .data
+            .extend_from_slice(format!(">>>>>>> {}\n", theirs_label).as_bytes());
+        return;
+    };
+    for line in lines {
+        merge.data.extend_from_slice(line);
+    }
+}
+
+// the lines of one side of a conflict, ending the last one so the marker starts a new line
+fn push_lines(data: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        data.extend_from_slice(line);
+    }
+    if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        data.push(b'\n');
+    }
+}
+
+// split into lines that keep their line ending, so the merge reproduces the files exactly
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/*
+match the lines of `a` to lines of `b` along a longest common subsequence.
+Returns, for each line of `a`, the index of the line of `b` it is matched to.
+The common prefix and suffix are matched directly before the table is built for the rest.
+*/
+fn match_lines(a: &[&[u8]], b: &[&[u8]]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; a.len()];
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (n, matched) in matches.iter_mut().enumerate().take(prefix) {
+        *matched = Some(n);
+    }
+    for n in 0..suffix {
+        matches[a.len() - 1 - n] = Some(b.len() - 1 - n);
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let (rows, cols) = (a_mid.len() + 1, b_mid.len() + 1);
+    if a_mid.is_empty() || b_mid.is_empty() || rows * cols > MAX_TABLE_SIZE {
+        return matches;
+    }
+
+    // lengths of the common subsequences of every pair of suffixes
+    let mut table = vec![0u32; rows * cols];
+    for x in (0..a_mid.len()).rev() {
+        for y in (0..b_mid.len()).rev() {
+            table[x * cols + y] = if a_mid[x] == b_mid[y] {
+                table[(x + 1) * cols + y + 1] + 1
+            } else {
+                table[(x + 1) * cols + y].max(table[x * cols + y + 1])
+            };
+        }
+    }
+
+    let (mut x, mut y) = (0, 0);
+    while x < a_mid.len() && y < b_mid.len() {
+        if a_mid[x] == b_mid[y] {
+            matches[prefix + x] = Some(prefix + y);
+            (x, y) = (x + 1, y + 1);
+        } else if table[(x + 1) * cols + y] >= table[x * cols + y + 1] {
+            x += 1;
+        } else {
+            y += 1;
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let merged = merge_lines(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            "ours",
+            "theirs",
+        );
+        (String::from_utf8(merged.data).unwrap(), merged.conflicts)
+    }
+
+    const BASE: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    #[test]
+    fn a_side_left_unchanged_takes_the_other() {
+        let changed = "zero\none\n2\nthree\nfive";
+        assert_eq!(merge(BASE, BASE, changed), (changed.to_string(), 0));
+        assert_eq!(merge(BASE, changed, BASE), (changed.to_string(), 0));
+        assert_eq!(merge(BASE, BASE, BASE), (BASE.to_string(), 0));
+    }
+
+    #[test]
+    fn changes_to_different_lines_are_combined() {
+        let ours = "one\nTWO\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+        let expected = "one\nTWO\nthree\nfour\nFIVE\nsix\n";
+        assert_eq!(merge(BASE, ours, theirs), (expected.to_string(), 0));
+    }
+
+    #[test]
+    fn the_same_change_on_both_sides_is_taken_once() {
+        let changed = "one\ntwo\n3\nfour\nfive\n";
+        assert_eq!(merge(BASE, changed, changed), (changed.to_string(), 0));
+    }
+
+    #[test]
+    fn different_changes_to_the_same_lines_conflict() {
+        let ours = "one\ntwo\nours\nfour\nfive\n";
+        let theirs = "one\ntwo\ntheirs\nfour\nfive\n";
+        let expected =
+            "one\ntwo\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nfour\nfive\n";
+        assert_eq!(merge(BASE, ours, theirs), (expected.to_string(), 1));
+    }
+
+    #[test]
+    fn conflict_markers_start_on_their_own_line() {
+        let (merged, conflicts) = merge("", "ours", "theirs");
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged,
+            "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n"
+        );
+    }
+}
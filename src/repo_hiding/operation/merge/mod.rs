@@ -0,0 +1,444 @@
+mod diff3;
+pub mod resolve;
+
+use super::branch::{ensure_clean_worktree, get_branch, get_head, update_head, ExpectedRef};
+use super::revision::{
+    apply_revision, commit_tree, get_revision, is_ancestor, resolve_revision, set_file_mode,
+    store_tree,
+};
+use super::stage::{commit_entries, remove_worktree_file, reset_index};
+use crate::file_hiding::file_log::{retrieve_object, store_object, ObjectKind};
+use crate::file_hiding::index::{set_unmerged, write_entries, ConflictStages, IndexEntry};
+use crate::file_hiding::merge_state::{read_merge_head, store_merge_state};
+use crate::repo_hiding::data_type::{CommitMetadata, Hash};
+use crate::repo_hiding::utility::is_binary;
+use diff3::merge_lines;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/* why a path could not be merged automatically */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictKind {
+    Content,       // both sides changed the same lines
+    AddAdd,        // both sides added the file with different contents
+    ModifyDelete,  // one side changed the file, the other deleted it
+    Binary,        // both sides changed a binary file
+    Mode,          // both sides changed the mode of the file differently
+    FileDirectory, // the path is a file on one side and a directory on the other
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConflictKind::Content => "content",
+            ConflictKind::AddAdd => "add/add",
+            ConflictKind::ModifyDelete => "modify/delete",
+            ConflictKind::Binary => "binary",
+            ConflictKind::Mode => "mode",
+            ConflictKind::FileDirectory => "file/directory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/* what `merge_revision` did */
+#[derive(Debug)]
+pub enum MergeOutcome {
+    UpToDate,                               // the other commit is already part of HEAD
+    FastForward(Hash),                      // HEAD moved forward to the other commit
+    Merged(Hash),                           // a merge commit with two parents was made
+    Conflicts(Vec<(String, ConflictKind)>), // the working directory holds conflicting files
+}
+
+/*
+merge the commit named by `name` into HEAD.
+When HEAD is an ancestor of the other commit it is simply moved forward. Otherwise both sides
+are merged file by file against their merge base: a file changed on one side takes that change,
+and a file changed on both sides is merged line by line. If every file merges cleanly the result
+is committed with HEAD and the other commit as parents; otherwise the working directory is left
+with conflict markers in the conflicting files, and the merge is recorded as in progress until
+it is continued or aborted (see `resolve`).
+*/
+pub fn merge_revision(
+    name: &str,
+    author: &str,
+    message: Option<&str>,
+) -> Result<MergeOutcome, String> {
+    if read_merge_head()
+        .map_err(|e| format!("Failed to read merge state: {}", e))?
+        .is_some()
+    {
+        return Err(
+            "A merge is already in progress, finish it with `geet merge --continue` \
+            or abort it with `geet merge --abort`"
+                .to_string(),
+        );
+    }
+    let head = get_head()?.ok_or_else(|| "HEAD does not point to any commit".to_string())?;
+    let theirs = resolve_revision(name)?;
+    if is_ancestor(&theirs, &head)? {
+        return Ok(MergeOutcome::UpToDate);
+    }
+    ensure_clean_worktree(&theirs)?;
+
+    let base = merge_base(&head, &theirs)?;
+    if base.as_ref() == Some(&head) {
+        let reason = format!("merge {}: Fast-forward", name);
+        apply_revision(&theirs)?;
+        if let Err(e) = update_head(&theirs, &ExpectedRef::At(head.clone()), &reason) {
+            apply_revision(&head)?;
+            return Err(e);
+        }
+        return Ok(MergeOutcome::FastForward(theirs));
+    }
+
+    let merge = merge_trees(base.as_ref(), &head, &theirs, name)?;
+    let message = match message {
+        Some(message) => message.to_string(),
+        None if get_branch(&name.to_string()).is_ok() => format!("Merge branch '{}'", name),
+        None => format!("Merge commit '{}'", name),
+    };
+    if !merge.conflicts.is_empty() {
+        // conflicting paths stay staged as our version until they are resolved
+        let mut entries = merge.files;
+        for (path, stages) in &merge.stages {
+            if let Some(entry) = &stages.ours {
+                entries.insert(path.clone(), entry.clone());
+            }
+        }
+        let unmerged = merge
+            .stages
+            .into_iter()
+            .map(|(path, stages)| (path, stages.hashes()))
+            .collect();
+        write_merge(&head, &merge.writes, || {
+            write_entries(entries)
+                .and_then(|_| set_unmerged(unmerged))
+                .and_then(|_| store_merge_state(&theirs, &message))
+                .map_err(|e| format!("Failed to record the merge state: {}", e))
+        })?;
+        return Ok(MergeOutcome::Conflicts(merge.conflicts));
+    }
+
+    let tree_hash = store_tree(&merge.files)?;
+    let metadata = CommitMetadata {
+        author: author.to_string(),
+        message,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+    let reason = format!("merge {}: Merge made by the 'three-way' strategy.", name);
+    let parents = vec![head.clone(), theirs];
+    let commit_hash = write_merge(&head, &merge.writes, || {
+        commit_tree(tree_hash, parents, metadata, &reason)
+    })?;
+    reset_index(Some(&commit_hash))?;
+    Ok(MergeOutcome::Merged(commit_hash))
+}
+
+/*
+write the merged files to the working directory, which matches HEAD, so only paths whose merged
+contents differ are written, then record the result. If either fails the paths are put back to
+their HEAD version, so a failed merge leaves the working directory as it found it.
+*/
+fn write_merge<T>(
+    head: &Hash,
+    writes: &[WorktreeWrite],
+    record: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    // deletions go first, so a file can take the place of a directory that was removed
+    let (deletes, updates): (Vec<_>, Vec<_>) = writes.iter().partition(|(_, c, _)| c.is_none());
+    let written = deletes
+        .into_iter()
+        .chain(updates)
+        .try_for_each(|(path, contents, mode)| {
+            write_worktree_file(path, contents.as_deref(), *mode)
+                .map_err(|e| format!("Failed to update {}: {}", path, e))
+        });
+    let result = written.and_then(|_| record());
+    if result.is_err() {
+        let ours = commit_entries(head)?;
+        for (path, _, _) in writes {
+            let entry = ours.get(path);
+            let contents = entry.map(|entry| read_blob(&entry.hash)).transpose()?;
+            write_worktree_file(path, contents.as_deref(), entry.map(|entry| entry.mode))
+                .map_err(|e| format!("Failed to restore {}: {}", path, e))?;
+        }
+    }
+    result
+}
+
+/*
+the best common ancestor of two commits: a commit reachable from both that is not an ancestor
+of another such commit. None when the histories are unrelated.
+*/
+pub fn merge_base(a: &Hash, b: &Hash) -> Result<Option<Hash>, String> {
+    let from_a = ancestors(a)?;
+
+    // walk back from b, stopping at the first commits also reachable from a
+    let mut common = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![b.clone()];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        if from_a.contains(&hash) {
+            common.push(hash);
+        } else {
+            pending.extend(get_revision(&hash)?.parents);
+        }
+    }
+
+    for candidate in &common {
+        let mut is_best = true;
+        for other in common.iter().filter(|other| *other != candidate) {
+            if is_ancestor(candidate, other)? {
+                is_best = false;
+                break;
+            }
+        }
+        if is_best {
+            return Ok(Some(candidate.clone()));
+        }
+    }
+    Ok(None)
+}
+
+fn ancestors(hash: &Hash) -> Result<HashSet<Hash>, String> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![hash.clone()];
+    while let Some(hash) = pending.pop() {
+        if seen.insert(hash.clone()) {
+            pending.extend(get_revision(&hash)?.parents);
+        }
+    }
+    Ok(seen)
+}
+
+/* the three versions of a conflicting path, with their modes */
+struct Stages {
+    base: Option<IndexEntry>,
+    ours: Option<IndexEntry>,
+    theirs: Option<IndexEntry>,
+}
+
+impl Stages {
+    fn hashes(&self) -> ConflictStages {
+        let hash = |entry: &Option<IndexEntry>| entry.as_ref().map(|entry| entry.hash.clone());
+        ConflictStages {
+            base: hash(&self.base),
+            ours: hash(&self.ours),
+            theirs: hash(&self.theirs),
+        }
+    }
+}
+
+/* a path of the working directory with its new contents and mode, None deletes it */
+type WorktreeWrite = (String, Option<Vec<u8>>, Option<u32>);
+
+/* the merged snapshot of three versions of a tree */
+struct FileMerge {
+    files: BTreeMap<String, IndexEntry>, // merged contents of every path that merged cleanly
+    writes: Vec<WorktreeWrite>,          // working directory changes
+    conflicts: Vec<(String, ConflictKind)>,
+    stages: BTreeMap<String, Stages>, // the three versions of each conflicting path
+}
+
+impl FileMerge {
+    fn conflict(&mut self, path: &str, kind: ConflictKind, stages: Stages) {
+        self.conflicts.push((path.to_string(), kind));
+        self.stages.insert(path.to_string(), stages);
+    }
+}
+
+// merge the trees of two commits against the tree of their merge base
+fn merge_trees(
+    base: Option<&Hash>,
+    ours: &Hash,
+    theirs: &Hash,
+    theirs_label: &str,
+) -> Result<FileMerge, String> {
+    let base_files = match base {
+        Some(base) => commit_entries(base)?,
+        None => BTreeMap::new(),
+    };
+    merge_files(
+        &base_files,
+        &commit_entries(ours)?,
+        &commit_entries(theirs)?,
+        theirs_label,
+    )
+}
+
+fn merge_files(
+    base: &BTreeMap<String, IndexEntry>,
+    ours: &BTreeMap<String, IndexEntry>,
+    theirs: &BTreeMap<String, IndexEntry>,
+    theirs_label: &str,
+) -> Result<FileMerge, String> {
+    let mut merge = FileMerge {
+        files: BTreeMap::new(),
+        writes: Vec::new(),
+        conflicts: Vec::new(),
+        stages: BTreeMap::new(),
+    };
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let stages = Stages {
+            base: b.cloned(),
+            ours: o.cloned(),
+            theirs: t.cloned(),
+        };
+
+        // a path changed on at most one side takes that side
+        let taken = if o == t || b == t {
+            Some(o)
+        } else if b == o {
+            Some(t)
+        } else {
+            None
+        };
+        if let Some(entry) = taken {
+            if let Some(entry) = entry {
+                merge.files.insert(path.clone(), entry.clone());
+            }
+            if entry != o {
+                let contents = entry.map(|entry| read_blob(&entry.hash)).transpose()?;
+                merge
+                    .writes
+                    .push((path.clone(), contents, entry.map(|entry| entry.mode)));
+            }
+            continue;
+        }
+
+        // changed on both sides
+        let (o, t) = match (o, t) {
+            (Some(o), Some(t)) => (o, t),
+            (Some(kept), None) | (None, Some(kept)) => {
+                merge.conflict(path, ConflictKind::ModifyDelete, stages);
+                if o.is_none() {
+                    let contents = read_blob(&kept.hash)?;
+                    merge
+                        .writes
+                        .push((path.clone(), Some(contents), Some(kept.mode)));
+                }
+                continue;
+            }
+            (None, None) => unreachable!("a path deleted on both sides is taken as is"),
+        };
+
+        let base_data = b
+            .map(|b| read_blob(&b.hash))
+            .transpose()?
+            .unwrap_or_default();
+        let ours_data = read_blob(&o.hash)?;
+        let theirs_data = read_blob(&t.hash)?;
+        // a mode changed on one side only takes that side, like the contents
+        let base_mode = b.map(|b| b.mode);
+        let (mode, mode_conflict) = if o.mode == t.mode || base_mode == Some(t.mode) {
+            (o.mode, false)
+        } else if base_mode == Some(o.mode) {
+            (t.mode, false)
+        } else {
+            (o.mode, true)
+        };
+        if is_binary(&base_data) || is_binary(&ours_data) || is_binary(&theirs_data) {
+            // the working directory keeps our version
+            merge.conflict(path, ConflictKind::Binary, stages);
+            continue;
+        }
+
+        let merged = merge_lines(&base_data, &ours_data, &theirs_data, "HEAD", theirs_label);
+        if merged.conflicts > 0 {
+            let kind = if b.is_some() {
+                ConflictKind::Content
+            } else {
+                ConflictKind::AddAdd
+            };
+            merge.conflict(path, kind, stages);
+        } else if mode_conflict {
+            // the working directory gets the merged contents with our mode
+            merge.conflict(path, ConflictKind::Mode, stages);
+        } else {
+            let hash = store_object(ObjectKind::Blob, &merged.data)
+                .map_err(|e| format!("Failed to store merged {}: {}", path, e))?;
+            merge
+                .files
+                .insert(path.clone(), IndexEntry::new(hash, mode));
+        }
+        merge
+            .writes
+            .push((path.clone(), Some(merged.data), Some(mode)));
+    }
+
+    /*
+    a path cannot be a file on one side and hold files on the other. Both the file and the paths
+    below it become conflicts, and the working directory keeps our side of them.
+    */
+    let present: BTreeSet<&String> = merge.files.keys().chain(merge.stages.keys()).collect();
+    let mut clashing = BTreeSet::new();
+    for path in &present {
+        for dir in Path::new(path.as_str()).ancestors().skip(1) {
+            let dir = dir.to_string_lossy().to_string();
+            if present.contains(&dir) {
+                clashing.insert(dir);
+                clashing.insert(path.to_string());
+            }
+        }
+    }
+    for path in clashing {
+        merge.files.remove(&path);
+        merge.writes.retain(|(written, _, _)| *written != path);
+        merge
+            .conflicts
+            .retain(|(conflicted, _)| *conflicted != path);
+        let stages = Stages {
+            base: base.get(&path).cloned(),
+            ours: ours.get(&path).cloned(),
+            theirs: theirs.get(&path).cloned(),
+        };
+        merge.conflict(&path, ConflictKind::FileDirectory, stages);
+    }
+    merge.conflicts.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(merge)
+}
+
+fn read_blob(hash: &Hash) -> Result<Vec<u8>, String> {
+    retrieve_object(hash)
+        .map(|object| object.data)
+        .map_err(|_| format!("blob with hash {} not found", hash))
+}
+
+// write or delete a file of the working directory, setting its mode when one is given
+fn write_worktree_file(
+    path: &str,
+    contents: Option<&[u8]>,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let path = Path::new(path);
+    match contents {
+        Some(contents) => {
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+            match mode {
+                Some(mode) => set_file_mode(path, mode),
+                None => Ok(()),
+            }
+        }
+        None => remove_worktree_file(path),
+    }
+}
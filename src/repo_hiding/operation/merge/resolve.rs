@@ -0,0 +1,96 @@
+use super::{read_blob, write_worktree_file};
+use crate::file_hiding::index::{get_unmerged, index_key};
+use crate::file_hiding::merge_state::{clear_merge_state, read_merge_head, read_merge_message};
+use crate::repo_hiding::data_type::{CommitMetadata, Hash};
+use crate::repo_hiding::operation::branch::get_head;
+use crate::repo_hiding::operation::revision::{apply_revision, commit_tree};
+use crate::repo_hiding::operation::stage::{commit_entries, index_tree};
+use std::path::Path;
+
+/* the side of a conflict to take a file from */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictSide {
+    Ours,
+    Theirs,
+}
+
+// the commit being merged, or None when no merge stopped on conflicts
+pub fn merge_in_progress() -> Result<Option<Hash>, String> {
+    read_merge_head().map_err(|e| format!("Failed to read merge state: {}", e))
+}
+
+fn require_merge() -> Result<Hash, String> {
+    merge_in_progress()?.ok_or_else(|| "There is no merge in progress".to_string())
+}
+
+/*
+finish a merge once every conflict is resolved (marked with `geet add`).
+The merge commit holds the staged snapshot, which starts out as the cleanly merged files, and
+has HEAD and the merged commit as parents.
+*/
+pub fn continue_merge(message: Option<&str>, author: &str) -> Result<Hash, String> {
+    let merge_head = require_merge()?;
+    let unmerged = get_unmerged();
+    if !unmerged.is_empty() {
+        let paths: Vec<String> = unmerged.keys().map(|path| format!("  {}", path)).collect();
+        return Err(format!(
+            "These paths still have conflicts, resolve them and mark them with `geet add`:\n{}",
+            paths.join("\n")
+        ));
+    }
+
+    let head = get_head()?.ok_or_else(|| "HEAD does not point to any commit".to_string())?;
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => read_merge_message().map_err(|e| format!("Failed to read merge message: {}", e))?,
+    };
+    let reason = format!(
+        "commit (merge): {}",
+        message.lines().next().unwrap_or_default()
+    );
+    let metadata = CommitMetadata {
+        author: author.to_string(),
+        message,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+    let tree_hash = index_tree()?;
+    let commit_hash = commit_tree(tree_hash, vec![head, merge_head], metadata, &reason)?;
+    clear_merge_state().map_err(|e| format!("Failed to clear merge state: {}", e))?;
+    Ok(commit_hash)
+}
+
+// give up on the merge in progress and restore the working directory and the index to HEAD
+pub fn abort_merge() -> Result<(), String> {
+    require_merge()?;
+    let head = get_head()?.ok_or_else(|| "HEAD does not point to any commit".to_string())?;
+    apply_revision(&head)?;
+    clear_merge_state().map_err(|e| format!("Failed to clear merge state: {}", e))
+}
+
+// replace a conflicting file in the working directory by our or their version of it
+pub fn checkout_conflict_side(path: &str, side: ConflictSide) -> Result<(), String> {
+    let path = index_key(Path::new(path));
+    let unmerged = get_unmerged();
+    let stages = unmerged
+        .get(&path)
+        .ok_or_else(|| format!("Path {} has no merge conflict", path))?;
+    let (hash, whose, commit) = match side {
+        ConflictSide::Ours => (&stages.ours, "our", get_head()?),
+        ConflictSide::Theirs => (&stages.theirs, "their", Some(require_merge()?)),
+    };
+    let hash = hash
+        .as_ref()
+        .ok_or_else(|| format!("Path {} does not have {} version", path, whose))?;
+
+    // the stages only keep hashes, the mode comes from the tree of that side
+    let mode = match commit {
+        Some(commit) => commit_entries(&commit)?
+            .get(&path)
+            .filter(|entry| entry.hash == *hash)
+            .map(|entry| entry.mode),
+        None => None,
+    };
+    write_worktree_file(&path, Some(&read_blob(hash)?), mode)
+        .map_err(|e| format!("Failed to update {}: {}", path, e))
+}
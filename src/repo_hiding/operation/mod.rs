@@ -1,3 +1,6 @@
 pub mod branch;
+pub mod maintenance;
+pub mod merge;
 pub mod repo;
 pub mod revision;
+pub mod stage;
@@ -1,24 +1,33 @@
-use crate::file_hiding::file_log::{copy_dir, deserialize_metadata, store_object};
-use crate::file_hiding::index;
-use crate::file_hiding::ref_log::store_ref;
-use crate::repo_hiding::data_type::{Commit, Hash, RefType, Tree};
+use crate::file_hiding::file_log::{
+    copy_dir, copy_object, list_loose_objects, list_objects, store_object, ObjectKind,
+};
+use crate::file_hiding::hasher_checker::{algorithm_of, HashAlgorithm};
+use crate::file_hiding::pack::copy_packs;
+use crate::file_hiding::ref_log::{list_stored_refs_in, retrieve_ref_in};
+use crate::file_hiding::user_data::UserDataManager;
+use crate::repo_hiding::data_type::{Commit, Hash, Ref, RefType, Tree};
 use crate::repo_hiding::data_type::{CommitMetadata, RepositoryConfig};
+use crate::repo_hiding::operation::branch::reflog::{record_ref_update, record_ref_update_in};
 use crate::repo_hiding::operation::branch::{
-    checkout_commit, create_head, create_ref, get_head, get_ref, update_head, update_ref,
+    create_head, create_ref, current_branch, delete_ref, ensure_clean_worktree, get_head,
+    list_refs, migrate_flat_refs, parse_stored_ref, swap_ref_in, update_head, ExpectedRef,
 };
-use crate::repo_hiding::operation::revision::create_revision;
-use crate::BASE_DIR;
-use clap::builder::Str;
-use serde::{Deserialize, Serialize};
+use crate::repo_hiding::operation::revision::{apply_revision, is_ancestor};
+use crate::{BASE_DIR, CONFIG_FILE};
 use std::collections::HashSet;
-use std::fmt;
 use std::fs::{self, File};
 use std::io;
-use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 
-pub fn init_repo(name: &String, default_branch: &String) -> Result<(), String> {
+// the name remote-tracking refs are recorded under, whichever remote path was used
+const REMOTE_NAME: &str = "origin";
+
+pub fn init_repo(
+    name: &str,
+    default_branch: &str,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), String> {
     let path = ".";
 
     // Check if repository already exists
@@ -27,11 +36,12 @@ pub fn init_repo(name: &String, default_branch: &String) -> Result<(), String> {
     }
 
     // Create directory structure with proper error handling
-    let refs_path = format!("{}/.geet/refs", path);
     let objects_path = format!("{}/.geet/objects", path);
 
-    fs::create_dir_all(&refs_path)
-        .map_err(|e| format!("Failed to create refs directory: {}", e))?;
+    for namespace in [RefType::Branch.namespace(), RefType::Tag.namespace()] {
+        fs::create_dir_all(format!("{}/.geet/{}", path, namespace))
+            .map_err(|e| format!("Failed to create refs directory: {}", e))?;
+    }
     fs::create_dir_all(&objects_path)
         .map_err(|e| format!("Failed to create objects directory: {}", e))?;
 
@@ -39,10 +49,20 @@ pub fn init_repo(name: &String, default_branch: &String) -> Result<(), String> {
     File::create(format!("{}/.geet/index", path))
         .map_err(|e| format!("Failed to create index file: {}", e))?;
 
+    // Store the repository configuration before any object is hashed
+    let config = RepositoryConfig {
+        name: name.to_string(),
+        default_branch: default_branch.to_string(),
+        hash_algorithm,
+    };
+    UserDataManager::new()
+        .store_config(CONFIG_FILE, config.serialize().as_bytes())
+        .map_err(|e| format!("Failed to store repository configuration: {:?}", e))?;
+
     // Create initial empty tree and store it
     let empty_tree = Tree::new();
     let tree_serialized = empty_tree.serialize();
-    let tree_hash = store_object(&tree_serialized)
+    let tree_hash = store_object(ObjectKind::Tree, tree_serialized.as_bytes())
         .map_err(|e| format!("Failed to store initial tree: {}", e))?;
 
     // Create initial commit
@@ -52,25 +72,19 @@ pub fn init_repo(name: &String, default_branch: &String) -> Result<(), String> {
         timestamp: chrono::Utc::now().to_rfc3339(),
     };
 
-    let initial_commit = Commit::new_commit(tree_hash, None, metadata);
+    let initial_commit = Commit::new_commit(tree_hash, Vec::new(), metadata);
     let commit_serialized = initial_commit.serialize();
-    let commit_hash = store_object(&commit_serialized)
+    let commit_hash = store_object(ObjectKind::Commit, commit_serialized.as_bytes())
         .map_err(|e| format!("Failed to store initial commit: {}", e))?;
 
-    // Create HEAD and default branch
-    create_head();
+    // Create the default branch and point HEAD at it
     create_ref(
         RefType::Branch,
-        default_branch.clone(),
+        default_branch.to_string(),
         Some(commit_hash.clone()),
+        "init",
     )?;
-    update_head(&commit_hash);
-
-    // Create and store repository configuration
-    let config = RepositoryConfig {
-        name: name.clone(),
-        default_branch: default_branch.clone(),
-    };
+    create_head(default_branch)?;
 
     println!("Repository configuration initialized:");
     println!("{}", config.serialize());
@@ -98,7 +112,7 @@ pub fn validate_remote_repo(path: &str) -> std::io::Result<()> {
 
 pub fn clone_repo(remote_path: &String, local_path: &String) -> Result<(), String> {
     // Validate the remote repository
-    validate_remote_repo(&remote_path)
+    validate_remote_repo(remote_path)
         .map_err(|e| format!("Remote repository validation failed: {}", e))?;
 
     // // Ensure the local path does not already exist
@@ -110,7 +124,7 @@ pub fn clone_repo(remote_path: &String, local_path: &String) -> Result<(), Strin
     // fs::create_dir_all(&local_path).map_err(|e| format!("Failed to create directory: {}", e))?;
 
     // Ensure the folder is empty
-    if !is_folder_empty(&local_path) {
+    if !is_folder_empty(local_path) {
         return Err("The current directory is not empty.".to_string());
     }
 
@@ -123,31 +137,30 @@ pub fn clone_repo(remote_path: &String, local_path: &String) -> Result<(), Strin
     let remote_geet_path = PathBuf::from(remote_path).join(".geet");
     let local_geet_path = PathBuf::from(local_path).join(".geet");
 
-    // Copy the .geet directory
-    copy_dir(remote_geet_path.clone(), local_geet_path.clone())
-        .map_err(|e| format!("Failed to copy .geet directory: {}", e))?;
-
-    // Copy references
-    let remote_refs_path = PathBuf::from(remote_path).join("refs");
-    let local_refs_path = PathBuf::from(local_path).join("refs");
-
-    copy_dir(remote_refs_path, local_refs_path)
-        .map_err(|e| format!("Failed to copy refs: {}", e))?;
+    // Copy the .geet directory, refs and config included, so the clone keeps the remote's hash algorithm
     copy_dir(remote_geet_path, local_geet_path)
         .map_err(|e| format!("Failed to copy .geet directory: {}", e))?;
 
     //copy_refs(&remote_path, &local_path).map_err(|e| format!("Failed to copy refs: {}", e))?;
-
-    // Fetch the remote HEAD hash
-    let remote_head_ref = get_ref(&"HEAD".to_string())?;
-    let remote_head_hash = remote_head_ref
-        .commit_hash
-        .clone()
-        .ok_or_else(|| "Remote HEAD reference is missing.".to_string())?;
-
-    // Update local HEAD to point to the remote HEAD hash
-    update_head(&remote_head_hash);
-    checkout_commit(&remote_head_hash)?;
+    migrate_flat_refs()?;
+    let reason = format!("clone: from {}", remote_path);
+    update_tracking_refs(remote_path, &reason)?;
+
+    // The copied HEAD already follows the remote's current branch, so only the files are missing
+    let remote_head_hash =
+        get_head()?.ok_or_else(|| "Remote HEAD reference is missing.".to_string())?;
+    apply_revision(&remote_head_hash)?;
+
+    // The remote's reflogs describe its history, the clone starts its own
+    let logs_path = PathBuf::from(local_path).join(".geet/logs");
+    if logs_path.exists() {
+        fs::remove_dir_all(&logs_path).map_err(|e| format!("Failed to reset reflogs: {}", e))?;
+    }
+    if let Some(branch) = current_branch()? {
+        let branch_ref = format!("{}/{}", RefType::Branch.namespace(), branch);
+        record_ref_update(&branch_ref, None, Some(remote_head_hash.clone()), &reason)?;
+    }
+    record_ref_update("HEAD", None, Some(remote_head_hash), &reason)?;
 
     println!("Repository successfully cloned to {}", local_path);
     Ok(())
@@ -256,32 +269,54 @@ pub fn push_repo(local_path: &String, remote_path: &String) -> Result<(), String
     println!("Repository successfully pushed to {}", remote_path);
     Ok(())
 }*/
-/// Get the list of object hashes from a directory
+/// Get the list of object hashes from an objects directory, sharded or flat
 pub fn get_object_hashes<P: AsRef<Path>>(dir_path: P) -> io::Result<HashSet<String>> {
-    let mut hashes = HashSet::new();
+    list_objects(dir_path.as_ref())
+}
+
+/// Copy only the packs and loose objects that the destination objects directory is missing
+pub fn copy_new_objects(src: PathBuf, dest: PathBuf) -> io::Result<()> {
+    for pack in copy_packs(&src, &dest)? {
+        println!("Copied new pack: {}", pack);
+    }
+
+    let src_hashes = list_loose_objects(&src)?;
+    let dest_hashes = get_object_hashes(&dest)?;
+
+    for hash in src_hashes.difference(&dest_hashes) {
+        copy_object(&src, &dest, hash)?;
+        println!("Copied new object: {}", hash);
+    }
+
+    Ok(())
+}
+
+/// Get the list of file names directly inside a directory
+fn get_file_names<P: AsRef<Path>>(dir_path: P) -> io::Result<HashSet<String>> {
+    let mut names = HashSet::new();
     let dir_path = dir_path.as_ref(); // Convert P into &Path
 
     if let Ok(entries) = fs::read_dir(dir_path) {
         for entry in entries {
             let entry = entry?;
             if entry.file_type()?.is_file() {
-                hashes.insert(entry.file_name().to_string_lossy().to_string());
+                names.insert(entry.file_name().to_string_lossy().to_string());
             }
         }
     }
-    Ok(hashes)
+    Ok(names)
 }
 
 pub fn copy_new_or_updated_files(src: PathBuf, dest: PathBuf) -> io::Result<()> {
-    let src_hashes = get_object_hashes(&src)?;
-    let dest_hashes = get_object_hashes(&dest)?;
+    let src_names = get_file_names(&src)?;
+    let dest_names = get_file_names(&dest)?;
 
-    for hash in src_hashes.difference(&dest_hashes) {
-        let src_file = src.join(hash);
-        let dest_file = dest.join(hash);
+    for name in src_names.difference(&dest_names) {
+        let src_file = src.join(name);
+        let dest_file = dest.join(name);
 
         fs::copy(&src_file, &dest_file)?;
-        println!("Copied new object: {}", hash);
+        println!("Copied new file: {}", name);
     }
 
     Ok(())
@@ -291,35 +326,54 @@ pub fn pull_repo(remote_path: &String, local_path: &String) -> Result<(), String
     // Validate the remote repository
     validate_remote_repo(remote_path)
         .map_err(|e| format!("Remote repository validation failed: {}", e))?;
+    ensure_same_hash_algorithm(local_path, remote_path)?;
 
     // Fetch the current HEAD hash
     let local_head_hash = get_head()?; // This should return Option<Hash>
-    let remote_head_ref = get_ref(&"HEAD".to_string())?; // Adjust get_ref to return Ref or Option<Ref>
-    let remote_head_hash = remote_head_ref
-        .commit_hash
-        .clone()
-        .ok_or_else(|| "Remote HEAD is missing.".to_string())?;
+    let remote_head_hash = read_remote_head(remote_path)?;
 
-    // Skip pull if the hashes are the same
-    if local_head_hash == Some(remote_head_hash.clone()) {
-        println!("No new changes to pull.");
-        return Ok(());
-    }
-
-    // Synchronize objects
+    // Synchronize objects, even when HEAD is unchanged new tags may have been created
     let remote_objects_path = PathBuf::from(remote_path).join(".geet/objects");
     let local_objects_path = PathBuf::from(local_path).join(".geet/objects");
-    copy_new_or_updated_files(remote_objects_path, local_objects_path)
+    copy_new_objects(remote_objects_path, local_objects_path)
         .map_err(|e| format!("Failed to pull objects: {}", e))?;
 
-    // Synchronize references
-    let remote_refs_path = PathBuf::from(remote_path).join(".geet/refs");
-    let local_refs_path = PathBuf::from(local_path).join(".geet/refs");
-    copy_new_or_updated_files(remote_refs_path, local_refs_path)
-        .map_err(|e| format!("Failed to pull refs: {}", e))?;
+    // Record the remote's branches as remote-tracking refs and take over its new tags
+    let reason = format!("pull: from {}", remote_path);
+    update_tracking_refs(remote_path, &reason)?;
+    let local_tags = list_refs(RefType::Tag)?;
+    for tag in read_remote_refs(remote_path, RefType::Tag)? {
+        if local_tags.iter().all(|local| local.name != tag.name) {
+            write_repo_ref(local_path, &tag, &ExpectedRef::Absent, &reason)?;
+            println!("New tag: {}", tag.name);
+        }
+    }
 
-    // Update HEAD
-    update_head(&remote_head_hash);
+    // Move HEAD forward to the remote's HEAD; local commits the remote lacks must be merged
+    let local_is_ahead = match &local_head_hash {
+        Some(local_hash) if is_ancestor(&remote_head_hash, local_hash)? => true,
+        Some(local_hash) if !is_ancestor(local_hash, &remote_head_hash)? => {
+            return Err(format!(
+                "Local and remote history have diverged, run `geet merge {}` to combine them",
+                remote_head_hash
+            ));
+        }
+        _ => false,
+    };
+    if local_is_ahead {
+        println!("No new commits to pull.");
+    } else {
+        ensure_clean_worktree(&remote_head_hash)?;
+        let expected = ExpectedRef::from_read(local_head_hash.clone());
+        apply_revision(&remote_head_hash)?;
+        if let Err(e) = update_head(&remote_head_hash, &expected, &reason) {
+            // put the working directory back at the commit HEAD still points to
+            if let Some(local_head_hash) = &local_head_hash {
+                apply_revision(local_head_hash)?;
+            }
+            return Err(e);
+        }
+    }
 
     println!("Repository successfully pulled from {}", remote_path);
     Ok(())
@@ -329,31 +383,162 @@ pub fn push_repo(local_path: &String, remote_path: &String) -> Result<(), String
     // Validate the remote repository
     validate_remote_repo(remote_path)
         .map_err(|e| format!("Remote repository validation failed: {}", e))?;
+    ensure_same_hash_algorithm(local_path, remote_path)?;
 
     // Push only new or updated objects
     let local_objects_path = PathBuf::from(local_path).join(".geet/objects");
     let remote_objects_path = PathBuf::from(remote_path).join(".geet/objects");
-    copy_new_or_updated_files(local_objects_path, remote_objects_path)
+    copy_new_objects(local_objects_path, remote_objects_path)
         .map_err(|e| format!("Failed to push new objects: {}", e))?;
 
-    // Push only updated references
-    let local_refs_path = PathBuf::from(local_path).join(".geet/refs");
-    let remote_refs_path = PathBuf::from(remote_path).join(".geet/refs");
-    copy_new_or_updated_files(local_refs_path, remote_refs_path)
-        .map_err(|e| format!("Failed to push refs: {}", e))?;
+    // Push new branches and fast-forward existing ones, never dropping the remote's commits
+    let remote_branches = read_remote_refs(remote_path, RefType::Branch)?;
+    let remote_current = read_repo_ref(remote_path, "HEAD")?.target;
+    for branch in list_refs(RefType::Branch)? {
+        let Some(local_hash) = &branch.commit_hash else {
+            continue;
+        };
+        // its working directory would no longer match the branch
+        if remote_current.as_ref() == Some(&branch.full_name()) {
+            let remote_hash = read_remote_head(remote_path)?;
+            if remote_hash != *local_hash {
+                println!("Rejected {}: it is checked out in the remote repository", branch.name);
+            }
+            continue;
+        }
+        let remote = remote_branches.iter().find(|remote| remote.name == branch.name);
+        match remote {
+            None => println!("New branch: {}", branch.name),
+            Some(remote) => match &remote.commit_hash {
+                Some(remote_hash) if remote_hash == local_hash => continue,
+                Some(remote_hash) if !is_ancestor(remote_hash, local_hash)? => {
+                    println!(
+                        "Rejected {}: the remote branch has commits that are not in the local one",
+                        branch.name
+                    );
+                    continue;
+                }
+                _ => println!("Updated branch: {}", branch.name),
+            },
+        }
+        // the remote branch must not move between the check above and the update
+        let expected = match remote {
+            None => ExpectedRef::Absent,
+            Some(remote) => ExpectedRef::from_read(remote.commit_hash.clone()),
+        };
+        write_repo_ref(remote_path, &branch, &expected, "push")?;
+    }
 
-    // Update remote HEAD
-    if let Some(local_head_hash) = get_head()? {
-        update_ref(&"HEAD".to_string(), local_head_hash);
-        println!("Remote HEAD updated successfully.");
-    } else {
-        return Err("Local HEAD is missing.".to_string());
+    // Push only new tags, an existing tag is never moved
+    let remote_tags = read_remote_refs(remote_path, RefType::Tag)?;
+    for tag in list_refs(RefType::Tag)? {
+        if remote_tags.iter().all(|remote| remote.name != tag.name) {
+            write_repo_ref(remote_path, &tag, &ExpectedRef::Absent, "push")?;
+            println!("New tag: {}", tag.name);
+        }
     }
+    update_tracking_refs(remote_path, &format!("push: to {}", remote_path))?;
 
     println!("Repository successfully pushed to {}", remote_path);
     Ok(())
 }
 
+// read the commit the remote's HEAD points to, following it to the remote's current branch
+fn read_remote_head(remote_path: &str) -> Result<Hash, String> {
+    let head = read_repo_ref(remote_path, "HEAD")?;
+    let commit_hash = match head.target {
+        Some(target) => read_repo_ref(remote_path, &target)?.commit_hash,
+        None => head.commit_hash,
+    };
+    commit_hash.ok_or_else(|| "Remote HEAD is missing.".to_string())
+}
+
+// read a ref by its full name from the repository at the given path
+fn read_repo_ref(repo_path: &str, full_name: &str) -> Result<Ref, String> {
+    let geet_path = PathBuf::from(repo_path).join(".geet");
+    if geet_path.join("refs/HEAD").is_file() {
+        return Err(format!(
+            "The repository at {} still uses flat refs, run any geet command in it to upgrade it",
+            repo_path
+        ));
+    }
+    let stored = retrieve_ref_in(&geet_path, full_name)
+        .map_err(|e| format!("Failed to read remote ref {}: {}", full_name, e))?;
+    parse_stored_ref(full_name, stored)
+}
+
+// every ref of the given kind in the repository at the given path
+fn read_remote_refs(repo_path: &str, kind: RefType) -> Result<Vec<Ref>, String> {
+    let geet_path = PathBuf::from(repo_path).join(".geet");
+    let refs = list_stored_refs_in(&geet_path, kind.namespace())
+        .map_err(|e| format!("Failed to read remote refs: {}", e))?;
+    refs.into_iter()
+        .map(|(name, stored)| parse_stored_ref(&name, stored))
+        .collect()
+}
+
+// write a ref into the namespace of its kind in the repository at the given path,
+// recording the update in that repository's reflog
+fn write_repo_ref(
+    repo_path: &str,
+    data: &Ref,
+    expected: &ExpectedRef,
+    reason: &str,
+) -> Result<(), String> {
+    let geet_path = PathBuf::from(repo_path).join(".geet");
+    let full_name = data.full_name();
+    let old_hash = read_repo_ref(repo_path, &full_name)
+        .ok()
+        .and_then(|old| old.commit_hash);
+    if old_hash.is_some() && old_hash == data.commit_hash {
+        return Ok(());
+    }
+
+    let old_hash = swap_ref_in(&geet_path, &full_name, Some(data), expected)?;
+    record_ref_update_in(&geet_path, &full_name, old_hash, data.commit_hash.clone(), reason)
+}
+
+/*
+mirror the remote's branches as `refs/remotes/origin/<branch>`, dropping tracking refs
+of branches the remote no longer has
+*/
+fn update_tracking_refs(remote_path: &str, reason: &str) -> Result<(), String> {
+    let remote_branches = read_remote_refs(remote_path, RefType::Branch)?;
+    let prefix = format!("{}/", REMOTE_NAME);
+    for tracking in list_refs(RefType::Remote)? {
+        let Some(branch) = tracking.name.strip_prefix(&prefix) else {
+            continue;
+        };
+        if remote_branches.iter().all(|remote| remote.name != branch) {
+            delete_ref(&tracking.full_name(), &ExpectedRef::Any)?;
+        }
+    }
+
+    for branch in remote_branches {
+        let tracking = Ref {
+            ref_type: RefType::Remote,
+            name: format!("{}{}", prefix, branch.name),
+            commit_hash: branch.commit_hash,
+            target: None,
+        };
+        write_repo_ref(BASE_DIR, &tracking, &ExpectedRef::Any, reason)?;
+    }
+    Ok(())
+}
+
+// objects of a SHA-1 and a SHA-256 repository can never refer to each other, so they must not be mixed
+fn ensure_same_hash_algorithm(local_path: &str, remote_path: &str) -> Result<(), String> {
+    let local = algorithm_of(Path::new(local_path));
+    let remote = algorithm_of(Path::new(remote_path));
+    if local != remote {
+        return Err(format!(
+            "Hash algorithm mismatch: local repository uses {} but remote uses {}",
+            local, remote
+        ));
+    }
+    Ok(())
+}
+
 fn is_folder_empty(path: &str) -> bool {
     let mut entries = fs::read_dir(path).unwrap();
     entries.next().is_none()
@@ -0,0 +1,82 @@
+use crate::file_hiding::file_log::{find_objects, list_objects, retrieve_object};
+use crate::repo_hiding::data_type::Hash;
+use crate::OBJECTS_DIR;
+use std::path::Path;
+
+// shortest prefix accepted in place of a full hash
+pub const MIN_PREFIX_LEN: usize = 4;
+// length short hashes are printed with unless more digits are needed to keep them unique
+const DEFAULT_SHORT_LEN: usize = 7;
+
+// resolve a full or abbreviated hash to the single object it names
+pub fn resolve_hash(prefix: &str) -> Result<Hash, String> {
+    let prefix = prefix.to_ascii_lowercase();
+    if prefix.len() < MIN_PREFIX_LEN || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "{} is not an object hash, at least {} hex digits are needed",
+            prefix, MIN_PREFIX_LEN
+        ));
+    }
+
+    let candidates = find_objects(Path::new(OBJECTS_DIR), &prefix)
+        .map_err(|e| format!("Failed to look up {}: {}", prefix, e))?;
+    match candidates.as_slice() {
+        [] => Err(format!("No object found for {}", prefix)),
+        [hash] => Ok(hash.clone()),
+        _ => {
+            let lines: Vec<String> = candidates
+                .iter()
+                .map(|hash| match retrieve_object(hash) {
+                    Ok(object) => format!("  {} {}", hash, object.kind),
+                    Err(_) => format!("  {}", hash),
+                })
+                .collect();
+            Err(format!(
+                "Short hash {} is ambiguous, candidates are:\n{}",
+                prefix,
+                lines.join("\n")
+            ))
+        }
+    }
+}
+
+/* shortens hashes to the fewest digits that no other object in the repository shares */
+pub struct ShortHashes {
+    hashes: Vec<Hash>, // every object hash, sorted
+}
+
+impl ShortHashes {
+    pub fn new() -> Result<Self, String> {
+        let mut hashes: Vec<Hash> = list_objects(Path::new(OBJECTS_DIR))
+            .map_err(|e| format!("Failed to list objects: {}", e))?
+            .into_iter()
+            .collect();
+        hashes.sort();
+        Ok(Self { hashes })
+    }
+
+    pub fn shorten(&self, hash: &str) -> String {
+        let common = |other: &Hash| {
+            other
+                .bytes()
+                .zip(hash.bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+
+        // in sorted order the closest prefixes belong to the direct neighbours
+        let pos = self.hashes.partition_point(|other| other.as_str() < hash);
+        let next = match self.hashes.get(pos) {
+            Some(other) if other == hash => pos + 1,
+            _ => pos,
+        };
+        let mut len = DEFAULT_SHORT_LEN;
+        if pos > 0 {
+            len = len.max(common(&self.hashes[pos - 1]) + 1);
+        }
+        if let Some(other) = self.hashes.get(next) {
+            len = len.max(common(other) + 1);
+        }
+        hash[..len.min(hash.len())].to_string()
+    }
+}
@@ -1,80 +1,39 @@
-use crate::file_hiding::file_log::{retrieve_object, store_object};
-use crate::file_hiding::index;
+use crate::file_hiding::file_log::retrieve_object;
+use crate::file_hiding::index::{read_entries, MODE_EXECUTABLE};
 use crate::repo_hiding::data_type::{Hash, Tree};
-use crate::{BASE_DIR, GEET_DIR};
+use crate::repo_hiding::operation::stage::remove_worktree_file;
+use crate::BASE_DIR;
 use std::fs;
-use std::io::{Result, Write};
+use std::io::{self, Result, Write};
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
-
-pub fn read_cwd() -> Option<Hash> {
-    read_cwd_helper(BASE_DIR).unwrap_or(None)
-}
-
-fn read_cwd_helper(path: &str) -> Result<Option<Hash>> {
-    let children = fs::read_dir(path)?;
-    let mut tree = Tree::new();
-    let staged_files: HashSet<PathBuf> = index::get_staged_files()
-        .into_iter()
-        .map(|p| p.canonicalize().unwrap_or(p))  // Normalize paths
-        .collect();
-
-    for child in children {
-        let path = child?.path();
-        let canonical_path = path.canonicalize()?;  // Normalize path
-        let path_string = strip_path(&path);
-
-        // ignore the ./geet folder
-        if path_string.starts_with(GEET_DIR) {
-            continue;
-        }
-
-        // Only process if file is staged or is a directory
-        if path.is_dir() || staged_files.contains(&canonical_path) {
-            let hash = if path.is_dir() {
-                read_cwd_helper(&path_string)?
-            } else {
-                Some(store_file(&path_string))
-            };
-
-            if let Some(hash) = hash {
-                let file_name = path.file_name().unwrap().to_str().unwrap();
-                tree.add_node(file_name.to_string(), hash, path.is_dir());
-            }
-        }
-    }
-
-    if tree.nodes.is_empty() {
-        Ok(None)
-    } else {
-        let serialized = tree.serialize();
-        let hash = store_object(&serialized)?;
-        Ok(Some(hash))
-    }
-}
 
 // pub fn update_cwd(hash: &Hash) {
 //     delete_cwd(Path::new(BASE_DIR)).unwrap();
 //     update_cwd_helper(BASE_DIR, hash).unwrap();
 // }
+/*
+replace the tracked files of the working directory by the files of a tree: files the index
+tracks but the tree lacks are deleted, the files of the tree are written over their old
+versions. Untracked and ignored files are left alone.
+*/
 pub fn update_cwd(hash: &Hash) -> Result<()> {
-    delete_cwd(Path::new(BASE_DIR))?;
+    let tracked = read_entries()?;
+    let target = super::tree_entries(hash).map_err(io::Error::other)?;
+    delete_cwd(tracked.keys().filter(|key| !target.contains_key(*key)))?;
     update_cwd_helper(BASE_DIR, hash)
 }
 
 fn update_cwd_helper(path: &str, hash: &Hash) -> Result<()> {
-    let serialized = retrieve_object(hash)?;
-    let tree = Tree::deserialize(&serialized);
+    let object = retrieve_object(hash)?;
+    let tree = Tree::deserialize(&object)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", hash, e)))?;
 
     for node in tree.nodes {
-        let path = PathBuf::from(path).join(node.name);
+        let path = PathBuf::from(path).join(&node.name);
         let path_string = strip_path(&path);
 
         if node.is_dir {
-            if !path.exists() {
-                fs::create_dir_all(&path)?;
-            }
-            fs::create_dir(path)?;
+            fs::create_dir_all(&path)?;
             update_cwd_helper(&path_string, &node.hash)?;
         } else {
             if let Some(parent) = path.parent() {
@@ -83,44 +42,52 @@ fn update_cwd_helper(path: &str, hash: &Hash) -> Result<()> {
             if path.exists() {
                 fs::remove_file(&path)?;
             }
-            let contents = retrieve_object(&node.hash)?;
-            let mut file = fs::File::create(path)?;
-            file.write_all(contents.as_bytes())?;
+            let blob = retrieve_object(&node.hash)?;
+            let mut file = fs::File::create(&path)?;
+            file.write_all(&blob.data)?;
             file.flush()?;
+            set_file_mode(&path, node.file_mode())?;
         }
     }
 
     Ok(())
 }
 
-fn delete_cwd(path: &Path) -> Result<()> {
-    for child in fs::read_dir(&path)? {
-        let path = child?.path();
-
-        // ignore the ./geet folder
-        if path.starts_with(GEET_DIR) {
-            continue;
-        }
-
-        if path.is_dir() {
-            delete_cwd(&path)?;
-        } else {
-            fs::remove_file(path)?;
-        }
+// give a file the execute bits of its mode, where it may be read
+#[cfg(unix)]
+pub fn set_file_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    let current = permissions.mode();
+    let updated = if mode == MODE_EXECUTABLE {
+        current | (current & 0o444) >> 2
+    } else {
+        current & !0o111
+    };
+    if updated != current {
+        permissions.set_mode(updated);
+        fs::set_permissions(path, permissions)?;
     }
+    Ok(())
+}
 
-    if !path.ends_with(BASE_DIR) {
-        fs::remove_dir(path)?;
-    }
+#[cfg(not(unix))]
+pub fn set_file_mode(_path: &Path, _mode: u32) -> Result<()> {
     Ok(())
 }
 
-fn store_file(path: &String) -> Hash {
-    let data = fs::read_to_string(path).unwrap();
-    store_object(&data).unwrap()
+// delete the given files, then the directories they leave empty
+fn delete_cwd<'a>(keys: impl Iterator<Item = &'a String>) -> Result<()> {
+    for key in keys {
+        let path = Path::new(key);
+        if path.is_file() || path.is_symlink() {
+            remove_worktree_file(path)?;
+        }
+    }
+    Ok(())
 }
 
-fn strip_path(path: &PathBuf) -> String {
+fn strip_path(path: &Path) -> String {
     path.to_str()
         .map(|s| s.trim_start_matches("./").to_string())
         .unwrap_or_default()
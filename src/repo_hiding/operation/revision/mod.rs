@@ -1,56 +1,177 @@
+mod abbrev;
 mod cwd;
-use super::branch::{get_head, update_head};
-use crate::file_hiding::file_log::{retrieve_object, store_object};
-use crate::repo_hiding::data_type::{Commit, CommitMetadata, Hash};
-use cwd::{read_cwd, update_cwd};
+mod rev_parse;
+use super::branch::{get_head, update_head, ExpectedRef};
+use super::stage::{index_tree, reset_index};
+use crate::file_hiding::file_log::{retrieve_object, store_object, ObjectKind};
+use crate::file_hiding::index::{get_unmerged, IndexEntry};
+use crate::repo_hiding::data_type::{Commit, CommitMetadata, Hash, Tag, Tree};
+use cwd::update_cwd;
+use std::collections::{BTreeMap, HashSet};
 
-use crate::file_hiding::index::{clear_index, get_staged_files, is_stage_empty};
+pub use abbrev::ShortHashes;
+pub use cwd::set_file_mode;
+pub use rev_parse::{resolve_object, resolve_range, resolve_revision, CommitRange};
 
-// create a new revision with the given metadata
+// create a new revision of the staged snapshot with the given metadata
 pub fn create_revision(metadata: CommitMetadata) -> Result<Hash, String> {
-    if is_stage_empty() {
-        return Err("No changes to commit".to_string());
+    if !get_unmerged().is_empty() {
+        return Err("Cannot commit while paths still have merge conflicts".to_string());
     }
 
     // create a new commit object
-    let tree_hash = read_cwd().ok_or_else(|| "No changes to commit".to_string())?;
+    let tree_hash = index_tree()?;
     let parent_hash = get_head()?;
-    let commit = Commit::new_commit(tree_hash, parent_hash, metadata);
+    let unchanged = match &parent_hash {
+        Some(parent_hash) => get_revision(parent_hash)?.tree_hash == tree_hash,
+        None => tree_hash == store_tree(&BTreeMap::new())?,
+    };
+    if unchanged {
+        return Err("No changes to commit".to_string());
+    }
+    let reason = format!(
+        "commit: {}",
+        metadata.message.lines().next().unwrap_or_default()
+    );
+    commit_tree(tree_hash, parent_hash.into_iter().collect(), metadata, &reason)
+}
 
+// store a commit of the given tree on top of its parents and move HEAD to it,
+// as long as HEAD still points to the first parent
+pub fn commit_tree(
+    tree_hash: Hash,
+    parents: Vec<Hash>,
+    metadata: CommitMetadata,
+    reason: &str,
+) -> Result<Hash, String> {
+    let expected = ExpectedRef::from_read(parents.first().cloned());
+    let commit = Commit::new_commit(tree_hash, parents, metadata);
 
     // store the commit object
     let serialized = commit.serialize();
-    let commit_hash = store_object(&serialized).unwrap();
+    let commit_hash = store_object(ObjectKind::Commit, serialized.as_bytes())
+        .map_err(|e| format!("Failed to store commit: {}", e))?;
 
     // update HEAD
-    update_head(&commit_hash);
+    update_head(&commit_hash, &expected, reason)?;
+    Ok(commit_hash)
+}
 
-    // Clear the index after successful commit
-    clear_index().unwrap_or_else(|e| eprintln!("Warning: Failed to clear index: {}", e));
+/*
+store the tree objects for a snapshot given as slash-separated file paths and their staged blobs,
+one tree per directory. Returns the hash of the root tree.
+*/
+pub fn store_tree(files: &BTreeMap<String, IndexEntry>) -> Result<Hash, String> {
+    let mut root = TreeBuilder::default();
+    for (path, entry) in files {
+        let mut dir = &mut root;
+        let mut parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let name = parts
+            .pop()
+            .ok_or_else(|| format!("Invalid path '{}' in snapshot", path))?;
+        for part in parts {
+            dir = dir.dirs.entry(part.to_string()).or_default();
+        }
+        dir.files.insert(name.to_string(), entry.clone());
+    }
+    root.store().map_err(|e| format!("Failed to store tree: {}", e))
+}
 
-    Ok(commit_hash)
+#[derive(Default)]
+struct TreeBuilder {
+    dirs: BTreeMap<String, TreeBuilder>,
+    files: BTreeMap<String, IndexEntry>,
+}
+
+impl TreeBuilder {
+    fn store(&self) -> std::io::Result<Hash> {
+        let mut tree = Tree::new();
+        for (name, dir) in &self.dirs {
+            tree.add_node(name.clone(), dir.store()?, true);
+        }
+        for (name, entry) in &self.files {
+            tree.add_file(name.clone(), entry.hash.clone(), entry.mode);
+        }
+        store_object(ObjectKind::Tree, tree.serialize().as_bytes())
+    }
+}
+
+// the files of a tree by slash-separated path, with their blobs and modes
+pub fn tree_entries(tree_hash: &Hash) -> Result<BTreeMap<String, IndexEntry>, String> {
+    let mut entries = BTreeMap::new();
+    tree_entries_helper("", tree_hash, &mut entries)?;
+    Ok(entries)
+}
+
+fn tree_entries_helper(
+    dir: &str,
+    tree_hash: &Hash,
+    entries: &mut BTreeMap<String, IndexEntry>,
+) -> Result<(), String> {
+    let object = retrieve_object(tree_hash)
+        .map_err(|_| format!("tree with hash {} not found", tree_hash))?;
+    let tree = Tree::deserialize(&object).map_err(|e| format!("{}: {}", tree_hash, e))?;
+    for node in tree.nodes {
+        let path = if dir.is_empty() {
+            node.name.clone()
+        } else {
+            format!("{}/{}", dir, node.name)
+        };
+        if node.is_dir {
+            tree_entries_helper(&path, &node.hash, entries)?;
+        } else {
+            let mode = node.file_mode();
+            entries.insert(path, IndexEntry::new(node.hash, mode));
+        }
+    }
+    Ok(())
 }
 
 // get the revision with the given hash
 pub fn get_revision(commit_hash: &String) -> Result<Commit, String> {
-    let serialized = retrieve_object(&commit_hash)
+    let object = retrieve_object(commit_hash)
         .map_err(|_| format!("commit with hash {} not found", commit_hash))?;
-    Ok(Commit::deserialize(&serialized))
+    Commit::deserialize(&object).map_err(|e| format!("{}: {}", commit_hash, e))
 }
 
-// get the parent that the revision is pointing to
-pub fn get_parent_revision(commit_hash: &String) -> Result<Option<Commit>, String> {
+// get the parents that the revision is pointing to, the first parent first
+pub fn get_parent_revision(commit_hash: &String) -> Result<Vec<Commit>, String> {
     let commit = get_revision(commit_hash)?;
-    if let Some(parent_hash) = commit.parent_hash {
-        Ok(Some(get_revision(&parent_hash)?))
-    } else {
-        Ok(None)
+    commit.parents.iter().map(get_revision).collect()
+}
+
+// follow annotated tags until reaching the object they point to, normally a commit
+pub fn peel_to_commit(hash: &Hash) -> Result<Hash, String> {
+    let mut hash = hash.clone();
+    loop {
+        let object = retrieve_object(&hash).map_err(|_| format!("object {} not found", hash))?;
+        if object.kind != ObjectKind::Tag {
+            return Ok(hash);
+        }
+        hash = Tag::deserialize(&object)?.target_hash;
+    }
+}
+
+// true if `ancestor` is `descendant` itself or one of its parents, grandparents, ...
+// following every parent of merge commits
+pub fn is_ancestor(ancestor: &Hash, descendant: &Hash) -> Result<bool, String> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![descendant.clone()];
+    while let Some(hash) = pending.pop() {
+        if hash == *ancestor {
+            return Ok(true);
+        }
+        if seen.insert(hash.clone()) {
+            pending.extend(get_revision(&hash)?.parents);
+        }
     }
+    Ok(false)
 }
 
-// apply the changes from the revision to the working directory
+// apply the changes from the revision to the working directory and the index
 pub fn apply_revision(commit_hash: &String) -> Result<(), String> {
     let commit = get_revision(commit_hash)?;
-    update_cwd(&commit.tree_hash);
-    Ok(())
+    update_cwd(&commit.tree_hash)
+        .map_err(|e| format!("Failed to update working directory: {}", e))?;
+    reset_index(Some(commit_hash))
 }
@@ -0,0 +1,251 @@
+use super::abbrev::{resolve_hash, MIN_PREFIX_LEN};
+use super::{get_revision, peel_to_commit};
+use crate::file_hiding::file_log::retrieve_object;
+use crate::repo_hiding::data_type::{Hash, Tree};
+use crate::repo_hiding::operation::branch::reflog::reflog_entry;
+use crate::repo_hiding::operation::branch::{get_ref_hash, resolve_ref_name};
+
+/* the commits named by `A..B`: reachable from `to` but not from `from` */
+#[derive(Debug, Clone)]
+pub struct CommitRange {
+    pub from: Option<Hash>, // None for a single revision, which includes all of its history
+    pub to: Hash,
+}
+
+// resolve a revision expression such as `main`, `origin/main`, `v1.0`, `HEAD~3`, `a1b2c3d^` or `HEAD@{2}` to a commit;
+// tags are followed to the commit they point to
+pub fn resolve_revision(expr: &str) -> Result<Hash, String> {
+    let hash = peel_to_commit(&resolve(expr)?)?;
+    get_revision(&hash).map_err(|e| format!("{} is not a commit: {}", expr, e))?;
+    Ok(hash)
+}
+
+// resolve an expression to any object: a revision, a `<rev>:<path>` inside a commit, or a blob or tree hash
+pub fn resolve_object(expr: &str) -> Result<Hash, String> {
+    match expr.split_once(':') {
+        Some((rev, path)) => {
+            if rev.is_empty() {
+                return Err(format!("Missing revision before ':' in {}", expr));
+            }
+            let commit = get_revision(&resolve_revision(rev)?)?;
+            lookup_path(&commit.tree_hash, path)
+                .map_err(|e| format!("Path '{}' does not exist in {}: {}", path, rev, e))
+        }
+        None => resolve(expr),
+    }
+}
+
+// resolve `A..B`, `A..` or `..B` (the missing side is HEAD); a single revision gives a range without a start
+pub fn resolve_range(expr: &str) -> Result<CommitRange, String> {
+    match expr.split_once("..") {
+        Some((from, to)) => {
+            let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+            Ok(CommitRange {
+                from: Some(resolve_revision(&or_head(from))?),
+                to: resolve_revision(&or_head(to))?,
+            })
+        }
+        None => Ok(CommitRange {
+            from: None,
+            to: resolve_revision(expr)?,
+        }),
+    }
+}
+
+/* a revision expression taken apart, before anything in it is looked up */
+#[derive(Debug, PartialEq)]
+struct Revision<'a> {
+    base: &'a str,         // `HEAD`, a ref name or an object hash
+    reflog: Option<usize>, // `@{n}`: where the base pointed n updates ago
+    steps: Vec<Step>,      // the `~n` and `^n` suffixes, in order
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Ancestor(usize), // `~n`: follow the first parent n times
+    Parent(usize),   // `^n`: the n-th parent, `^0` being the commit itself
+}
+
+/*
+parse `<base>` followed by any number of `~[n]`, `^[n]` and a leading `@{n}`.
+The base is `HEAD` (or `@`), a short or full ref name, or a full or abbreviated object hash.
+*/
+fn parse_revision(expr: &str) -> Result<Revision<'_>, String> {
+    let split = suffix_start(expr);
+    let (base, mut rest) = expr.split_at(split);
+    let base = if base.is_empty() || base == "@" {
+        "HEAD"
+    } else {
+        base
+    };
+
+    let mut reflog = None;
+    if let Some(after) = rest.strip_prefix("@{") {
+        let (n, after) = after
+            .split_once('}')
+            .ok_or_else(|| format!("Unterminated @{{...}} in {}", expr))?;
+        let n: usize = n
+            .parse()
+            .map_err(|_| format!("Invalid reflog index '{}' in {}", n, expr))?;
+        reflog = Some(n);
+        rest = after;
+    }
+
+    let mut steps = Vec::new();
+    while let Some(op) = rest.chars().next() {
+        if op != '~' && op != '^' {
+            return Err(format!("Invalid revision {}", expr));
+        }
+        let digits_end = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + 1);
+        let count = match &rest[1..digits_end] {
+            "" => 1,
+            digits => digits
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid number '{}' in {}", digits, expr))?,
+        };
+        steps.push(if op == '~' {
+            Step::Ancestor(count)
+        } else {
+            Step::Parent(count)
+        });
+        rest = &rest[digits_end..];
+    }
+
+    Ok(Revision {
+        base,
+        reflog,
+        steps,
+    })
+}
+
+fn resolve(expr: &str) -> Result<Hash, String> {
+    let revision = parse_revision(expr)?;
+    let mut hash = resolve_base(revision.base)?;
+    if let Some(n) = revision.reflog {
+        hash = reflog_entry(revision.base, n)?;
+    }
+
+    for step in revision.steps {
+        hash = peel_to_commit(&hash)?;
+        match step {
+            Step::Ancestor(count) => {
+                for _ in 0..count {
+                    hash = nth_parent(&hash, 1)?;
+                }
+            }
+            // `^0` names the commit itself
+            Step::Parent(0) => {
+                get_revision(&hash)?;
+            }
+            Step::Parent(n) => hash = nth_parent(&hash, n)?,
+        }
+    }
+
+    Ok(hash)
+}
+
+// where the `~`, `^` and `@{` suffixes begin, or the end of the expression
+fn suffix_start(expr: &str) -> usize {
+    let modifier = expr.find(['~', '^']).unwrap_or(expr.len());
+    let reflog = expr.find("@{").unwrap_or(expr.len());
+    modifier.min(reflog)
+}
+
+fn resolve_base(name: &str) -> Result<Hash, String> {
+    if let Some(full_name) = resolve_ref_name(name) {
+        return get_ref_hash(&full_name)?
+            .ok_or_else(|| format!("Ref {} does not point to any commit", name));
+    }
+
+    let looks_like_hash =
+        name.len() >= MIN_PREFIX_LEN && name.chars().all(|c| c.is_ascii_hexdigit());
+    if looks_like_hash {
+        resolve_hash(name)
+    } else {
+        Err(format!("Unknown revision or ref {}", name))
+    }
+}
+
+// the n-th parent of a commit, counting from 1
+fn nth_parent(hash: &Hash, n: usize) -> Result<Hash, String> {
+    let commit = get_revision(hash)?;
+    commit
+        .parents
+        .get(n - 1)
+        .cloned()
+        .ok_or_else(|| format!("Commit {} has no parent {}", hash, n))
+}
+
+// follow a slash-separated path from a root tree to the blob or tree it names
+fn lookup_path(tree_hash: &Hash, path: &str) -> Result<Hash, String> {
+    let mut hash = tree_hash.clone();
+    let mut parent = "/".to_string();
+    for name in path
+        .split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+    {
+        let object = retrieve_object(&hash).map_err(|_| format!("tree {} not found", hash))?;
+        let tree =
+            Tree::deserialize(&object).map_err(|_| format!("{} is not a directory", parent))?;
+        hash = tree
+            .nodes
+            .into_iter()
+            .find(|node| node.name == name)
+            .map(|node| node.hash)
+            .ok_or_else(|| format!("no entry named {} in {}", name, parent))?;
+        parent = name.to_string();
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(expr: &str) -> (&str, Option<usize>, Vec<Step>) {
+        let revision = parse_revision(expr).unwrap();
+        (revision.base, revision.reflog, revision.steps)
+    }
+
+    #[test]
+    fn bare_names_have_no_suffixes() {
+        assert_eq!(parse("main"), ("main", None, vec![]));
+        assert_eq!(parse("origin/main"), ("origin/main", None, vec![]));
+        assert_eq!(parse("a1b2c3d"), ("a1b2c3d", None, vec![]));
+    }
+
+    #[test]
+    fn an_empty_base_or_at_sign_means_head() {
+        assert_eq!(parse("@"), ("HEAD", None, vec![]));
+        assert_eq!(parse("~2"), ("HEAD", None, vec![Step::Ancestor(2)]));
+        assert_eq!(parse("@{1}"), ("HEAD", Some(1), vec![]));
+    }
+
+    #[test]
+    fn suffixes_are_parsed_in_order() {
+        assert_eq!(parse("HEAD~3"), ("HEAD", None, vec![Step::Ancestor(3)]));
+        assert_eq!(
+            parse("main^2~"),
+            ("main", None, vec![Step::Parent(2), Step::Ancestor(1)])
+        );
+        assert_eq!(
+            parse("v1.0^^0"),
+            ("v1.0", None, vec![Step::Parent(1), Step::Parent(0)])
+        );
+        assert_eq!(
+            parse("main@{2}~1^"),
+            ("main", Some(2), vec![Step::Ancestor(1), Step::Parent(1)])
+        );
+    }
+
+    #[test]
+    fn malformed_suffixes_are_rejected() {
+        for expr in ["HEAD@{2", "HEAD@{x}", "HEAD~1x", "main@{1}@{2}"] {
+            assert!(parse_revision(expr).is_err(), "{} was accepted", expr);
+        }
+        let too_big = format!("HEAD~{}0", usize::MAX);
+        assert!(parse_revision(&too_big).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use super::branch::diff::{diff_entries, Diff};
+use super::branch::get_head;
+use super::revision::{get_revision, store_tree, tree_entries};
+use crate::file_hiding::index::{
+    self, get_unmerged, index_key, legacy_staged_files, read_entries, set_unmerged,
+    untracked_files, write_entries, IndexEntry, WorktreeChanges,
+};
+use crate::repo_hiding::data_type::Hash;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+// the snapshot of the files of a commit, as it would be staged
+pub fn commit_entries(commit_hash: &Hash) -> Result<BTreeMap<String, IndexEntry>, String> {
+    tree_entries(&get_revision(commit_hash)?.tree_hash)
+}
+
+// the snapshot of HEAD, empty before the first commit
+pub fn head_entries() -> Result<BTreeMap<String, IndexEntry>, String> {
+    match get_head()? {
+        Some(head) => commit_entries(&head),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+fn read_index_entries() -> Result<BTreeMap<String, IndexEntry>, String> {
+    read_entries().map_err(|e| format!("Failed to read index: {}", e))
+}
+
+fn write_index_entries(entries: BTreeMap<String, IndexEntry>) -> Result<(), String> {
+    write_entries(entries).map_err(|e| format!("Failed to write index: {}", e))
+}
+
+// make the index match the files of a commit (or nothing) and forget recorded conflicts
+pub fn reset_index(commit_hash: Option<&Hash>) -> Result<(), String> {
+    let entries = match commit_hash {
+        Some(commit_hash) => commit_entries(commit_hash)?,
+        None => BTreeMap::new(),
+    };
+    write_index_entries(entries)?;
+    set_unmerged(BTreeMap::new()).map_err(|e| format!("Failed to write index: {}", e))
+}
+
+// how the staged snapshot differs from HEAD: the changes the next commit would make
+pub fn staged_diff() -> Result<Diff, String> {
+    Ok(diff_entries(&read_index_entries()?, &head_entries()?))
+}
+
+// how the working directory differs from the staged snapshot
+pub fn worktree_changes() -> Result<WorktreeChanges, String> {
+    index::worktree_changes().map_err(|e| format!("Failed to compare working directory: {}", e))
+}
+
+// put back the HEAD version of every staged path under `path`, leaving the working directory as is
+pub fn unstage(path: &str) -> Result<(), String> {
+    let prefix = index_key(Path::new(path));
+    let head = head_entries()?;
+    let mut staged = read_index_entries()?;
+    let paths: BTreeSet<String> = head
+        .keys()
+        .chain(staged.keys())
+        .filter(|key| Path::new(key).starts_with(&prefix))
+        .cloned()
+        .collect();
+    if paths.is_empty() {
+        return Err("File or directory not found".to_string());
+    }
+
+    for key in paths {
+        match head.get(&key) {
+            Some(entry) => staged.insert(key, entry.clone()),
+            None => staged.remove(&key),
+        };
+    }
+    write_index_entries(staged)
+}
+
+// the tracked paths under `prefix`, a file or directory given by its index key
+fn tracked_under(staged: &BTreeMap<String, IndexEntry>, prefix: &str) -> Vec<String> {
+    staged
+        .keys()
+        .filter(|key| Path::new(key).starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+/*
+stop tracking every file under `path`: the files leave the staged snapshot, so the next commit
+deletes them, and unless `cached` they are deleted from the working directory as well.
+Unless `force`, a file is refused when removing it would lose changes: with `cached` when its
+staged version matches neither HEAD nor the working directory, otherwise when its staged version
+differs from HEAD or the working copy differs from the staged one.
+Returns the removed paths.
+*/
+pub fn remove_paths(path: &str, cached: bool, force: bool) -> Result<Vec<String>, String> {
+    let prefix = index_key(Path::new(path));
+    let mut staged = read_index_entries()?;
+    let removed = tracked_under(&staged, &prefix);
+    if removed.is_empty() {
+        return Err(format!("Path {} is not tracked", path));
+    }
+
+    if !force {
+        let head = head_entries()?;
+        let worktree = worktree_changes()?;
+        for key in &removed {
+            let staged_changed = head
+                .get(key)
+                .is_none_or(|entry| !entry.same_contents(&staged[key]));
+            let locally_modified = worktree.modified.contains(key);
+            let loses_changes = if cached {
+                staged_changed && locally_modified
+            } else {
+                staged_changed || locally_modified
+            };
+            if loses_changes {
+                return Err(format!(
+                    "{} has changes that would be lost, use --force to remove it anyway",
+                    key
+                ));
+            }
+        }
+    }
+
+    for key in &removed {
+        staged.remove(key);
+        if !cached {
+            remove_worktree_file(Path::new(key))
+                .map_err(|e| format!("Failed to remove {}: {}", key, e))?;
+        }
+    }
+    write_index_entries(staged)?;
+
+    // removing a conflicting path resolves its conflict
+    let mut unmerged = get_unmerged();
+    unmerged.retain(|key, _| !Path::new(key).starts_with(&prefix));
+    set_unmerged(unmerged).map_err(|e| format!("Failed to write index: {}", e))?;
+    Ok(removed)
+}
+
+// delete a file, then the directories it leaves empty
+pub fn remove_worktree_file(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    for dir in path.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() || !dir.is_dir() || fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+/*
+rename a tracked file or directory in the working directory and in the staged snapshot, keeping
+the staged contents of every file. A destination that is an existing directory receives the
+source under its own name. Returns the moved paths as (old, new) pairs.
+*/
+pub fn move_path(source: &str, destination: &str) -> Result<Vec<(String, String)>, String> {
+    let source_key = index_key(Path::new(source));
+    let mut destination_key = index_key(Path::new(destination));
+    if Path::new(destination).is_dir() {
+        let name = Path::new(&source_key)
+            .file_name()
+            .ok_or_else(|| format!("Cannot move {}", source))?;
+        destination_key = index_key(&Path::new(&destination_key).join(name));
+    }
+
+    if !Path::new(&source_key).exists() {
+        return Err(format!("Source {} does not exist", source));
+    }
+    if Path::new(&destination_key).exists() {
+        return Err(format!("Destination {} already exists", destination_key));
+    }
+    if Path::new(&destination_key).starts_with(&source_key) {
+        return Err(format!("Cannot move {} into itself", source));
+    }
+    if get_unmerged()
+        .keys()
+        .any(|key| Path::new(key).starts_with(&source_key))
+    {
+        return Err(format!(
+            "{} has merge conflicts, resolve them first",
+            source
+        ));
+    }
+
+    let mut staged = read_index_entries()?;
+    let moved: Vec<(String, String)> = tracked_under(&staged, &source_key)
+        .into_iter()
+        .map(|key| {
+            let rest = Path::new(&key)
+                .strip_prefix(&source_key)
+                .unwrap_or(Path::new(""));
+            let new_key = if rest.as_os_str().is_empty() {
+                destination_key.clone()
+            } else {
+                index_key(&Path::new(&destination_key).join(rest))
+            };
+            (key, new_key)
+        })
+        .collect();
+    if moved.is_empty() {
+        return Err(format!("Path {} is not tracked", source));
+    }
+
+    if let Some(parent) = Path::new(&destination_key)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::rename(&source_key, &destination_key)
+        .map_err(|e| format!("Failed to move {} to {}: {}", source, destination_key, e))?;
+    for (old, new) in &moved {
+        if let Some(entry) = staged.remove(old) {
+            staged.insert(new.clone(), entry);
+        }
+    }
+    write_index_entries(staged)?;
+    Ok(moved)
+}
+
+/*
+rebuild an index written before it recorded file contents: start from the snapshot of HEAD and
+stage the current contents of the paths the old index listed.
+*/
+pub fn upgrade_legacy_index() -> Result<(), String> {
+    let Some(staged) = legacy_staged_files().map_err(|e| format!("Failed to read index: {}", e))?
+    else {
+        return Ok(());
+    };
+    write_index_entries(head_entries()?)?;
+    for path in staged.iter().filter(|path| path.exists()) {
+        index::add(&path.to_string_lossy(), true)?;
+    }
+    Ok(())
+}
+
+// store the trees of the staged snapshot, returning the hash of the root tree
+pub fn index_tree() -> Result<Hash, String> {
+    store_tree(&read_index_entries()?)
+}
+
+// the blobs the index refers to, including every version of a conflicting path
+pub fn index_hashes() -> Result<HashSet<Hash>, String> {
+    let mut hashes: HashSet<Hash> = read_index_entries()?
+        .into_values()
+        .map(|entry| entry.hash)
+        .collect();
+    for stages in get_unmerged().into_values() {
+        hashes.extend(
+            [stages.base, stages.ours, stages.theirs]
+                .into_iter()
+                .flatten(),
+        );
+    }
+    Ok(hashes)
+}
+
+/*
+delete the untracked files that are not ignored, or with `include_ignored` the ignored ones too,
+and the directories they leave empty. With `dry_run` nothing is deleted. Returns the paths.
+*/
+pub fn clean(include_ignored: bool, dry_run: bool) -> Result<Vec<String>, String> {
+    let untracked = untracked_files(include_ignored)
+        .map_err(|e| format!("Failed to list untracked files: {}", e))?;
+    if !dry_run {
+        for key in &untracked {
+            remove_worktree_file(Path::new(key))
+                .map_err(|e| format!("Failed to remove {}: {}", key, e))?;
+        }
+    }
+    Ok(untracked)
+}
@@ -1,7 +1,5 @@
-use std::collections::HashSet;
-use std::path::Path;
 use std::process::Command;
-use std::{fs, io};
+use std::time::Duration;
 
 enum DiffType {
     Add,
@@ -27,13 +25,35 @@ pub fn diff(path1: &String, path2: &String) -> String {
     String::from_utf8(output.stdout).unwrap()
 }
 
+// use the separator of the current platform, so `dir/file` works on Linux as well as Windows
 pub fn normalize_path(path: &str) -> String {
-    let mut normalized_path = path.replace('/', "\\"); // Replace all '/' with '\\'
+    path.replace(['/', '\\'], std::path::MAIN_SEPARATOR_STR)
+}
+
+// content is binary if it has a NUL byte or is not valid UTF-8, as text is shown and merged by line
+pub fn is_binary(data: &[u8]) -> bool {
+    data.contains(&0) || std::str::from_utf8(data).is_err()
+}
+
+// parse durations such as "30s", "15m", "12h", "3d" or "2w"; "now" means no delay at all
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    if value == "now" {
+        return Ok(Duration::ZERO);
+    }
 
-    // Ensure the path starts with '.\'
-  /*  if !normalized_path.starts_with(".\\") {
-        normalized_path = format!(".\\{}", normalized_path);
-    }*/
+    let invalid = || format!("Invalid duration '{}', expected e.g. 30s, 15m, 12h, 3d or 2w", value);
+    let split = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
 
-    normalized_path
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let seconds = amount.checked_mul(seconds).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(seconds))
 }